@@ -1,16 +1,993 @@
+use anyhow::Context;
+use clap::{CommandFactory, Parser, ValueEnum};
 use itertools::Itertools;
 use log::{debug, info, warn};
+use owo_colors::OwoColorize;
+use rand::Rng;
+use rayon::prelude::*;
 use septem::Roman;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::LinkedList;
-use std::env;
 use std::fmt;
+use std::io::IsTerminal;
+use std::time::Duration;
 
 const ROOT: &str = "https://esi.evetech.net/latest";
 const PARAM: &str = "?datasource=tranquility&language=en";
+const CONTACT: &str = "https://github.com/seb-odessa/best_route";
+/// Clouds smaller than this are solved exactly
+const BRUTE_FORCE_LIMIT: usize = 10;
+/// The names of the asteroid belts given by ESI
+const NAME_TEMPLATE: &str = "{system} {cloud} - Asteroid Belt {belt}";
+/// The brute force refuses to measure more routes than this
+const PERMUTATION_LIMIT: u64 = 10_000_000;
+/// Clouds larger than this take a while whatever the solver
+const SLOW_LIMIT: usize = 200;
+/// The auto solver routes the clusters separately when the gaps between them
+/// are this many times longer than the typical distance to the nearest place
+const CLUSTER_GAP: f64 = 5.0;
+/// The hops objective takes the belts this many meters from a visited one as visited,
+/// about the size of a grid
+const COVERAGE_RADIUS: f64 = 8_000_000.0;
+/// No system has planets beyond this, a larger cloud numeral is a broken name
+const CLOUD_NUMBER_LIMIT: u32 = 50;
+/// The schema of the route reports written by this build, the reports before
+/// the versioning come as 0. An incompatible change of RouteReport bumps it.
+const REPORT_VERSION: u32 = 1;
+
+/// Eve System Route Optimizer
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+struct Args {
+    /// Base URL of the ESI API, e.g. a local mock server
+    #[arg(long, env = "ESI_BASE", default_value = ROOT)]
+    esi_base: String,
+
+    /// Algorithm used to build the shortest route
+    #[arg(long, value_enum, default_value_t = Solver::Auto)]
+    solver: Solver,
+
+    /// Improve the routes of the solver by this local search at the end
+    #[arg(long, value_enum)]
+    polish: Option<Polish>,
+
+    /// Route by these stages in their order instead of the solver: the exact stage
+    /// solves the clouds it fits, the first route builder which runs builds the route
+    /// and the local searches improve it, e.g. `exact,nearest-insertion,3opt`
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        visible_alias = "solver-fallback-chain"
+    )]
+    solver_chain: Vec<Stage>,
+
+    /// What the solvers minimize
+    #[arg(long, value_enum, default_value_t = Objective::Distance)]
+    minimize: Objective,
+
+    /// Belts this many meters from a visited belt count as visited by `--minimize hops`
+    #[arg(long, value_name = "METERS", default_value_t = COVERAGE_RADIUS)]
+    coverage: f64,
+
+    /// Clouds smaller than this are brute forced by the size solver
+    #[arg(long, default_value_t = BRUTE_FORCE_LIMIT)]
+    brute_threshold: usize,
+
+    /// Clouds needing more permutations are routed by the nearest neighbor instead
+    #[arg(long, default_value_t = PERMUTATION_LIMIT)]
+    permutation_limit: u64,
+
+    /// Warn about the clouds of more places than this
+    #[arg(long, default_value_t = SLOW_LIMIT)]
+    slow_threshold: usize,
+
+    /// Output formats of the routes, may be repeated
+    #[arg(long, value_enum, default_values_t = [Format::Text])]
+    format: Vec<Format>,
+
+    /// Where the JSON output is written, `-` for the standard output
+    #[arg(long, value_name = "PATH", default_value = "-")]
+    json_to: String,
+
+    /// Compare the routes against the JSON report of an earlier run
+    #[arg(long, value_name = "OTHER_REPORT.json")]
+    diff: Option<std::path::PathBuf>,
+
+    /// Where the bookmarks are written, `-` for the standard output
+    #[arg(long, value_name = "PATH", default_value = "-")]
+    bookmarks_to: String,
+
+    /// Write a CSV row per routed system with the totals of its clouds, `-` for the standard output
+    #[arg(long, value_name = "PATH")]
+    summary_csv: Option<String>,
+
+    /// Rank the routed systems by the score of their belts, routes and security
+    #[arg(long)]
+    compare_systems: bool,
+
+    /// The weights of the score of `--compare-systems`: per belt, per Mm of the routes
+    /// (taken off the score) and per the security status
+    #[arg(long, value_name = "BELT,MM,SECURITY", default_value = "1,0.1,10")]
+    score_weights: ScoreWeights,
+
+    /// Where the Graphviz graph is written, `-` for the standard output
+    #[arg(long, value_name = "PATH", default_value = "-")]
+    dot_to: String,
+
+    /// How distances are printed
+    #[arg(long, value_enum, default_value_t = NumberStyle::Plain)]
+    number_style: NumberStyle,
+
+    /// Decimal places of the distances
+    #[arg(long, visible_alias = "output-precision", default_value_t = 0)]
+    precision: usize,
+
+    /// When the text output is colored
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// The same as `--color never`
+    #[arg(long)]
+    no_color: bool,
+
+    /// Order in which the clouds of a system are displayed
+    #[arg(long, value_enum, default_value_t = CloudOrder::Planet)]
+    order_by: CloudOrder,
+
+    /// The same as the default `--order-by planet`: the clouds as they lie in the system
+    #[arg(long, conflicts_with = "order_by")]
+    planet_order: bool,
+
+    /// Start the routes from the belt with this id or name,
+    /// or from the belt nearest to the `x,y,z` coordinates in meters
+    #[arg(long, value_name = "ID|NAME|X,Y,Z")]
+    route_from: Option<String>,
+
+    /// Start the routes from the belt nearest to the center of its cloud
+    #[arg(long, conflicts_with = "route_from")]
+    from_center: bool,
+
+    /// The preferred order of the belts by their ids or names: the nearest neighbor
+    /// takes the earlier of the equally close belts and starts from it on a tie
+    #[arg(long, value_name = "ID|NAME,...", value_delimiter = ',')]
+    belt_order_hint: Vec<String>,
+
+    /// Visit the belt with this id or name neither first nor last
+    #[arg(long, value_name = "ID|NAME|X,Y,Z")]
+    route_through: Option<String>,
+
+    /// Route separately the groups of belts farther than DIST Mm from each other
+    #[arg(long, value_name = "DIST")]
+    gap_split: Option<f64>,
+
+    /// Write every cloud as JSON into this directory
+    #[arg(long, value_name = "PATH")]
+    dump_cloud: Option<std::path::PathBuf>,
+
+    /// Route the clouds by the tour of this file: the belt ids in their order, a cloud
+    /// takes its own ones, or a TSPLIB tour of the instance written by `--format tsplib`
+    #[arg(long, value_name = "FILE")]
+    import_route: Option<std::path::PathBuf>,
+
+    /// Route only the belts listed in this file, one id per line.
+    /// Applied after the other filters, the clouds left empty are dropped.
+    #[arg(long, value_name = "PATH")]
+    belt_whitelist_file: Option<std::path::PathBuf>,
+
+    /// Where the belt positions come from, `file` takes them from `--belt-positions`
+    #[arg(long, value_enum, default_value_t = PositionSource::Esi,
+          requires_if("file", "belt_positions"))]
+    belt_position_source: PositionSource,
+
+    /// The belt positions for `--belt-position-source file`: the `id,x,y,z` lines of a CSV
+    /// or a JSON object of the ids and the positions. The other belts keep the ESI positions.
+    #[arg(long, value_name = "PATH")]
+    belt_positions: Option<std::path::PathBuf>,
+
+    /// Where `--format tsplib` writes the `<system_id>_<n>.tsp` instances of the clouds
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    tsplib_to: std::path::PathBuf,
+
+    /// Keep the loaded belts, systems and system names here and load them from it
+    /// next time: the directory of the JSON files or the SQLite database file
+    #[arg(long, value_name = "PATH")]
+    cache: Option<std::path::PathBuf>,
+
+    /// How `--cache` keeps the data, `sqlite` needs the build with the `sqlite` feature
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json)]
+    cache_backend: CacheBackend,
+
+    /// Keep the routes in the `--cache` too and reuse the ones solved less than this
+    /// many seconds ago for the same belts, options and version of the tool
+    #[arg(long, value_name = "SECONDS", requires = "cache")]
+    route_cache_ttl: Option<u64>,
+
+    /// List the belts of every cloud in this order before its route
+    #[arg(long, value_enum, value_name = "ORDER")]
+    list_belts: Option<BeltList>,
+
+    /// Print the summary of the distances between the belts of every cloud
+    #[arg(long)]
+    stats: bool,
+
+    /// Print the detour ratio of every route: its length over the straight line between its ends
+    #[arg(long)]
+    route_metric_summary: bool,
+
+    /// Print the id of every belt next to its name
+    #[arg(long)]
+    show_ids: bool,
+
+    /// Print the distance back to the first belt of every route
+    #[arg(long)]
+    round_trip: bool,
+
+    /// Finish every route at the station, either its id or the `x,y,z` coordinates in meters
+    #[arg(long, value_name = "STATION")]
+    end_at_station: Option<Endpoint>,
+
+    /// Route through the named position in meters too, e.g. a safe spot,
+    /// it joins the cloud with the closest place. Repeat for more points.
+    #[arg(long = "add-point", value_name = "NAME:X,Y,Z")]
+    add_points: Vec<Waypoint>,
+
+    /// Print the direction of every warp in the x-z plane
+    #[arg(long)]
+    bearings: bool,
+
+    /// Read the systems to route line by line, `set OPTION VALUE` changes the options
+    #[arg(long)]
+    interactive: bool,
+
+    /// Print the examples of the usage and exit
+    #[arg(long)]
+    examples: bool,
+
+    /// Print the whole chain of the causes when the run fails
+    #[arg(long)]
+    verbose_errors: bool,
+
+    /// Print everything the names resolve to and exit without routing
+    #[arg(long)]
+    resolve_only: bool,
+
+    /// Check the ESI data of the systems instead of routing them, fail on any violation
+    #[arg(long)]
+    audit: bool,
+
+    /// Load the systems and their belts into the `--cache` instead of routing them,
+    /// the next runs route them without the ESI
+    #[arg(long, requires = "cache")]
+    prewarm: bool,
+
+    /// Search the systems which names don't resolve exactly and offer the closest ones
+    #[arg(long)]
+    fuzzy: bool,
+
+    /// Load all the systems and then all their belts concurrently before routing
+    #[arg(long)]
+    prefetch_belt_ids: bool,
+
+    /// Number of the concurrent ESI requests the prefetch starts with,
+    /// it grows while the ESI answers and halves on the rate limits and server errors
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// The prefetch never has more ESI requests in flight than this
+    #[arg(long, default_value_t = 32)]
+    max_concurrency: usize,
+
+    /// Stop searching the routes of a system after this many seconds
+    /// and take the best one found so far
+    #[arg(long, value_name = "SECONDS", value_parser = positive)]
+    max_runtime: Option<f64>,
+
+    /// Route at most this many of the resolved systems
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Route the systems again every this many seconds until Ctrl-C
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    watch: Option<u64>,
+
+    /// Route the planets of the system instead of the asteroid belts
+    #[arg(long)]
+    planets: bool,
+
+    /// Template of the belt names, {cloud} is the planet numeral and {belt} is the belt number
+    #[arg(long, value_name = "TEMPLATE", default_value = NAME_TEMPLATE)]
+    name_template: NameTemplate,
+
+    /// Abort on the data anomalies which are only warned about otherwise
+    #[arg(long)]
+    strict: bool,
+
+    /// Number of the solver threads, 0 uses all the cores
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Contact sent to ESI in the User-Agent, e.g. an email or a character name
+    #[arg(long, default_value = CONTACT)]
+    contact: String,
+
+    /// How many times a failed ESI request is retried
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Delay before the first retry in milliseconds, doubled on every attempt
+    #[arg(long, default_value_t = 500)]
+    retry_delay: u64,
+
+    /// Random spread of the retry delays as a fraction of the delay
+    #[arg(long, default_value_t = 0.25)]
+    retry_jitter: f64,
+
+    /// Estimate the income of mining every cloud
+    #[arg(long)]
+    estimate_isk_per_hour: bool,
+
+    /// Warp speed of the ship in AU/s
+    #[arg(long, default_value_t = 3.0, value_parser = positive)]
+    warp_speed: f64,
+
+    /// Time for the ship to align before a warp in seconds
+    #[arg(long, default_value_t = 5.0, value_parser = non_negative)]
+    align_time: f64,
+
+    /// Ore mined from a belt in m3, a placeholder until belt contents are fetched
+    #[arg(long, default_value_t = 20000.0)]
+    ore_per_belt: f64,
+
+    /// Mining rate of the ship in m3/s
+    #[arg(long, default_value_t = 10.0)]
+    mining_rate: f64,
+
+    /// Price of the ore in ISK per m3
+    #[arg(long, default_value_t = 100.0)]
+    ore_price: f64,
+
+    /// Names or ids of the systems to route
+    names: Vec<String>,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum Solver {
+    /// Brute force for small clouds, the clusters routed one by one for the clustered
+    /// clouds and the nearest neighbor improved by the 2-opt for the others
+    #[default]
+    Auto,
+    /// Brute force for small clouds, nearest neighbor for the others
+    Size,
+    /// Greedy walk to the closest unvisited belt
+    NearestNeighbor,
+    /// Grow the route by the cheapest insertion of an unvisited belt
+    NearestInsertion,
+    /// Insert the belt farthest from the route at its cheapest position
+    FarthestInsertion,
+    /// Improve the nearest neighbor route by the variable depth edge exchange
+    #[value(name = "lk")]
+    LinKernighan,
+}
+
+/// The local search improving a route
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+enum Polish {
+    /// Reverse the segments of the route
+    #[value(name = "2opt")]
+    TwoOpt,
+    /// Reconnect the route cut in three places
+    #[value(name = "3opt")]
+    ThreeOpt,
+}
+
+/// A stage of the `--solver-chain`
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+enum Stage {
+    /// Brute force if the cloud fits the brute threshold and the permutation limit,
+    /// the route found ends the chain
+    Exact,
+    /// Build the route by the greedy walk to the closest unvisited belt
+    NearestNeighbor,
+    /// Build the route by the cheapest insertion of an unvisited belt
+    NearestInsertion,
+    /// Build the route by inserting the belt farthest from it
+    FarthestInsertion,
+    /// Build the route by the variable depth edge exchange
+    #[value(name = "lk")]
+    LinKernighan,
+    /// Improve the route by reversing its segments
+    #[value(name = "2opt")]
+    TwoOpt,
+    /// Improve the route by reconnecting it cut in three places
+    #[value(name = "3opt")]
+    ThreeOpt,
+}
+
+/// The way the auto solver routes a cloud
+#[derive(Debug, PartialEq, Clone)]
+enum Strategy {
+    /// The brute force
+    Exact,
+    /// The nearest neighbor improved by the 2-opt
+    Greedy,
+    /// The groups split by the gap routed one after another
+    Clustered(f64),
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum Objective {
+    /// The summed length of the warps
+    #[default]
+    Distance,
+    /// The time of the warps estimated by the warp model
+    Time,
+    /// The number of the warps, the belts within the coverage of a visited one are skipped
+    Hops,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct RouteOptions {
+    solver: Solver,
+    /// The stages routing instead of the solver unless empty
+    chain: Vec<Stage>,
+    /// The local search applied to the route of the solver
+    polish: Option<Polish>,
+    /// The cost of the hops the solvers minimize
+    objective: Objective,
+    /// The estimate of the warp times for the time objective
+    warp: WarpModel,
+    /// The places this close to a visited one are skipped by the hops objective
+    coverage: f64,
+    /// The place the route has to start from
+    start: Option<i32>,
+    /// The preferred order of the places for the nearest neighbor ties
+    hint: Vec<i32>,
+    /// The place the route has to pass between its first and last places
+    through: Option<i32>,
+    /// Clouds smaller than this are brute forced by the size solver
+    brute_threshold: usize,
+    /// The largest number of the routes the brute force may measure
+    permutation_limit: u64,
+    /// Routing the clouds larger than this is warned about
+    slow_threshold: usize,
+    /// The searches stop at this moment with the best route found so far
+    deadline: Option<std::time::Instant>,
+}
+impl Default for RouteOptions {
+    fn default() -> Self {
+        Self {
+            solver: Solver::default(),
+            chain: Vec::new(),
+            polish: None,
+            objective: Objective::default(),
+            warp: WarpModel::default(),
+            coverage: COVERAGE_RADIUS,
+            start: None,
+            hint: Vec::new(),
+            through: None,
+            brute_threshold: BRUTE_FORCE_LIMIT,
+            permutation_limit: PERMUTATION_LIMIT,
+            slow_threshold: SLOW_LIMIT,
+            deadline: None,
+        }
+    }
+}
+
+fn expired(deadline: Option<std::time::Instant>) -> bool {
+    deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline)
+}
+
+/// Number of the routes the brute force measures, saturates on overflow.
+/// Reversed routes are skipped unless the start is pinned.
+fn permutations(points: usize, pinned: bool) -> u64 {
+    let tails = (1..points as u64).fold(1u64, |count, n| count.saturating_mul(n));
+    if pinned {
+        tails
+    } else {
+        (tails.saturating_mul(points as u64) / 2).max(1)
+    }
+}
+
+/// Rearranges the items into the next permutation in the lexicographic order,
+/// returns false after the last one
+fn next_permutation(items: &mut [usize]) -> bool {
+    let Some(pivot) = (1..items.len()).rev().find(|i| items[i - 1] < items[*i]) else {
+        return false;
+    };
+    let successor = (pivot..items.len())
+        .rev()
+        .find(|i| items[*i] > items[pivot - 1])
+        .unwrap_or(pivot);
+    items.swap(pivot - 1, successor);
+    items[pivot..].reverse();
+    true
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum Format {
+    /// Human readable warp instructions
+    #[default]
+    Text,
+    /// A single JSON array with a report per system
+    Json,
+    /// A JSON array of the bookmarks of every belt in the route order
+    Bookmarks,
+    /// A JSON object per cloud and line, written as soon as the cloud and the ones before it
+    /// are routed, or once all of them are for `--order-by distance`
+    Ndjson,
+    /// A Graphviz graph of the routes, the belts are pinned at their x-z positions in Mm
+    Dot,
+    /// A TSPLIB instance per cloud for the external solvers
+    Tsplib,
+}
+
+/// The fixed end of the routes
+#[derive(Debug, PartialEq, Clone)]
+enum Endpoint {
+    /// The station loaded from the ESI
+    Station(i32),
+    /// The position in space, in meters
+    At(Position),
+}
+/// The weights of the mining efficiency of a system
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct ScoreWeights {
+    belt: f64,
+    megameter: f64,
+    security: f64,
+}
+impl ScoreWeights {
+    /// More belts and higher security raise the score, longer routes lower it
+    pub fn score(&self, system: &SystemReport) -> f64 {
+        let belts = system
+            .clouds
+            .iter()
+            .map(|cloud| cloud.route.len())
+            .sum::<usize>();
+        let distance = system
+            .clouds
+            .iter()
+            .map(|cloud| cloud.distance)
+            .sum::<f64>();
+        self.belt * belts as f64 - self.megameter * distance / 1e6
+            + self.security * f64::from(system.security_status)
+    }
+}
+impl std::str::FromStr for ScoreWeights {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let weights = value
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>();
+        match weights.as_deref() {
+            Ok([belt, megameter, security]) => Ok(Self {
+                belt: *belt,
+                megameter: *megameter,
+                security: *security,
+            }),
+            _ => anyhow::bail!("`{value}` is not the belt,Mm,security weights"),
+        }
+    }
+}
+
+/// A named position routed like a belt
+#[derive(Debug, PartialEq, Clone)]
+struct Waypoint {
+    name: String,
+    position: Position,
+}
+impl std::str::FromStr for Waypoint {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parsed = value.rsplit_once(':').and_then(|(name, coordinates)| {
+            let coordinates = coordinates
+                .split(',')
+                .map(|value| value.trim().parse::<f64>().ok())
+                .collect::<Option<Vec<f64>>>()?;
+            match coordinates.as_slice() {
+                [x, y, z] if !name.trim().is_empty() => Some(Self {
+                    name: name.trim().to_string(),
+                    position: Position::new(x, y, z),
+                }),
+                _ => None,
+            }
+        });
+        parsed.with_context(|| format!("`{value}` is not a point like NAME:x,y,z"))
+    }
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = value.trim().parse::<i32>() {
+            return Ok(Self::Station(id));
+        }
+        let coordinates = value
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>();
+        match coordinates.as_deref() {
+            Ok([x, y, z]) => Ok(Self::At(Position::new(x, y, z))),
+            _ => anyhow::bail!("`{value}` is neither a station id nor the x,y,z coordinates"),
+        }
+    }
+}
+
+/// The finite number above zero of the durations and the rates
+fn positive(value: &str) -> anyhow::Result<f64> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => Ok(number),
+        _ => anyhow::bail!("`{value}` is not a positive number"),
+    }
+}
+
+/// The finite number of zero or above of the durations which may be skipped
+fn non_negative(value: &str) -> anyhow::Result<f64> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number >= 0.0 => Ok(number),
+        _ => anyhow::bail!("`{value}` is not a non-negative number"),
+    }
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum CacheBackend {
+    /// A JSON file per belt and per system in the directory
+    #[default]
+    Json,
+    /// The tables of a SQLite database file
+    Sqlite,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum PositionSource {
+    /// The positions of the ESI
+    #[default]
+    Esi,
+    /// The positions of the `--belt-positions` file where it has them
+    File,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum CloudOrder {
+    /// By the planet id, i.e. by the orbit
+    #[default]
+    Planet,
+    /// By the number of belts, the smallest clouds first
+    Belts,
+    /// By the length of the shortest route, the shortest first
+    Distance,
+    /// By the name of the first belt
+    Name,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum NumberStyle {
+    /// Whole megameters: 1240000 Mm
+    #[default]
+    Plain,
+    /// Whole megameters with thousands separators: 1,240,000 Mm
+    Grouped,
+    /// Gigameters for the large values: 1.24 Gm
+    Scaled,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+enum BeltList {
+    /// By the planet numeral and the belt number
+    Name,
+    /// By the belt id
+    Id,
+    /// In the order of the shortest route
+    Route,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Default)]
+enum ColorChoice {
+    /// Color a terminal unless NO_COLOR is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+impl ColorChoice {
+    /// Decides the auto choice by the environment
+    pub fn resolve(self, no_color: bool) -> Self {
+        let disabled = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        match self {
+            _ if no_color => Self::Never,
+            Self::Auto if disabled || !std::io::stdout().is_terminal() => Self::Never,
+            Self::Auto => Self::Always,
+            choice => choice,
+        }
+    }
+}
+
+/// Styles of the text output, nothing is styled unless the color is always on
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Palette {
+    enabled: bool,
+}
+impl Palette {
+    pub fn new(choice: ColorChoice) -> Self {
+        Self {
+            enabled: choice == ColorChoice::Always,
+        }
+    }
+
+    fn paint(&self, text: &str, style: owo_colors::Style) -> String {
+        if self.enabled {
+            text.style(style).to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn header(&self, text: &str) -> String {
+        self.paint(text, owo_colors::Style::new().bold())
+    }
+
+    pub fn name(&self, text: &str) -> String {
+        self.paint(text, owo_colors::Style::new().cyan())
+    }
+
+    pub fn total(&self, text: &str) -> String {
+        self.paint(text, owo_colors::Style::new().green().bold())
+    }
+
+    pub fn warning(&self, text: &str) -> String {
+        self.paint(text, owo_colors::Style::new().yellow())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Retry {
+    attempts: u32,
+    delay: Duration,
+    jitter: f64,
+}
+impl Retry {
+    /// Exponential backoff spread by the jitter: delay * 2^attempt * (1 ± jitter)
+    pub fn delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let backoff = self.delay.as_secs_f64() * 2f64.powi(attempt as i32);
+        let spread = if self.jitter > 0.0 {
+            rng.gen_range(-self.jitter..=self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64(backoff * (1.0 + spread).max(0.0))
+    }
+}
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: Duration::from_millis(500),
+            jitter: 0.25,
+        }
+    }
+}
+
+/// A request to the ESI, the body of a POST is JSON
+#[derive(Debug, PartialEq, Clone)]
+struct HttpRequest {
+    method: reqwest::Method,
+    url: String,
+    body: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct HttpResponse {
+    status: u16,
+    body: String,
+}
+
+type HttpFuture<'a> =
+    std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<HttpResponse>> + Send + 'a>>;
+
+/// Sends the requests of the ESI. An error is a failure to get any response,
+/// the error statuses are the responses too.
+trait HttpClient: fmt::Debug + Send + Sync {
+    fn send(&self, request: HttpRequest) -> HttpFuture<'_>;
+}
+
+/// The HTTP client of the real ESI
+#[derive(Debug, Clone)]
+struct ReqwestClient {
+    client: reqwest::Client,
+}
+impl ReqwestClient {
+    pub fn new(contact: &str) -> Self {
+        let client = reqwest::Client::builder()
+            .user_agent(Esi::user_agent(contact))
+            .gzip(true)
+            .deflate(true)
+            .build()
+            .expect("Failed to build the HTTP client");
+        Self { client }
+    }
+}
+impl HttpClient for ReqwestClient {
+    fn send(&self, request: HttpRequest) -> HttpFuture<'_> {
+        Box::pin(async move {
+            let mut builder = self.client.request(request.method, &request.url);
+            if let Some(body) = request.body {
+                builder = builder
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body);
+            }
+            let response = builder.send().await?;
+            Ok(HttpResponse {
+                status: response.status().as_u16(),
+                body: response.text().await?,
+            })
+        })
+    }
+}
+
+/// The number of the requests in flight adapted by the additive increase and
+/// the multiplicative decrease: every success adds a request per the current
+/// limit of them, every throttled or failed response halves the limit
+#[derive(Debug)]
+struct Concurrency {
+    window: std::sync::Mutex<f64>,
+    max: usize,
+}
+impl Concurrency {
+    pub fn new(initial: usize, max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            window: std::sync::Mutex::new(initial.clamp(1, max) as f64),
+            max,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        *self.window.lock().unwrap() as usize
+    }
+
+    pub fn success(&self) {
+        let mut window = self.window.lock().unwrap();
+        *window = (*window + 1.0 / *window).min(self.max as f64);
+    }
+
+    pub fn backoff(&self) {
+        let mut window = self.window.lock().unwrap();
+        *window = (*window / 2.0).max(1.0);
+    }
+}
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self::new(8, 32)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Esi {
+    root: String,
+    client: std::sync::Arc<dyn HttpClient>,
+    retry: Retry,
+    /// Shared by the clones so all the concurrent loads adapt to the same responses
+    concurrency: std::sync::Arc<Concurrency>,
+}
+impl Esi {
+    pub fn new(root: &str) -> Self {
+        Self::with_contact(root, CONTACT)
+    }
+
+    pub fn with_contact(root: &str, contact: &str) -> Self {
+        Self::with_client(root, std::sync::Arc::new(ReqwestClient::new(contact)))
+    }
+
+    pub fn with_client(root: &str, client: std::sync::Arc<dyn HttpClient>) -> Self {
+        Self {
+            root: root.trim_end_matches('/').to_string(),
+            client,
+            retry: Retry::default(),
+            concurrency: std::sync::Arc::default(),
+        }
+    }
+
+    /// ESI asks to identify the application and the way to reach its maintainer
+    pub fn user_agent(contact: &str) -> String {
+        format!(
+            "{}/{} ({contact})",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        let mut esi = Self::with_contact(&args.esi_base, &args.contact);
+        esi.retry = Retry {
+            attempts: args.retries,
+            delay: Duration::from_millis(args.retry_delay),
+            jitter: args.retry_jitter,
+        };
+        esi.concurrency =
+            std::sync::Arc::new(Concurrency::new(args.concurrency, args.max_concurrency));
+        esi
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{path}/{PARAM}", self.root)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        self.fetch(HttpRequest {
+            method: reqwest::Method::GET,
+            url: url.to_string(),
+            body: None,
+        })
+        .await
+    }
+
+    pub async fn post<T: DeserializeOwned, B: Serialize>(
+        &self,
+        url: &str,
+        body: &B,
+    ) -> anyhow::Result<T> {
+        self.fetch(HttpRequest {
+            method: reqwest::Method::POST,
+            url: url.to_string(),
+            body: Some(serde_json::to_string(body)?),
+        })
+        .await
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, request: HttpRequest) -> anyhow::Result<T> {
+        let mut attempt = 0;
+        loop {
+            let (err, transient) = match self.client.send(request.clone()).await {
+                Ok(response) if (200..300).contains(&response.status) => {
+                    self.concurrency.success();
+                    return serde_json::from_str(&response.body).with_context(|| {
+                        format!("Failed to decode the response of {}", request.url)
+                    });
+                }
+                Ok(response) => (
+                    anyhow::anyhow!("HTTP status {} for url ({})", response.status, request.url),
+                    Self::is_transient_status(response.status),
+                ),
+                Err(err) => {
+                    let transient = err.downcast_ref::<reqwest::Error>().is_some_and(|err| {
+                        err.is_timeout() || err.is_connect() || err.is_request()
+                    });
+                    (err, transient)
+                }
+            };
+            if transient {
+                self.concurrency.backoff();
+            }
+            if attempt < self.retry.attempts && transient {
+                let delay = self.retry.delay(attempt, &mut rand::thread_rng());
+                warn!("{err}, retry in {delay:?}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            } else {
+                return Err(err);
+            }
+        }
+    }
+
+    fn is_transient_status(status: u16) -> bool {
+        // 420 is the ESI error limit
+        (500..600).contains(&status) || status == 420 || status == 429
+    }
+}
+impl Default for Esi {
+    fn default() -> Self {
+        Self::new(ROOT)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
 struct Position {
@@ -19,6 +996,11 @@ struct Position {
     z: f64,
 }
 impl Position {
+    /// No coordinate is NaN or infinite
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
     #[allow(dead_code)]
     pub fn new(x: &f64, y: &f64, z: &f64) -> Self {
         Self {
@@ -28,9 +1010,35 @@ impl Position {
         }
     }
 
+    #[allow(dead_code)]
+    pub fn from_array([x, y, z]: [f64; 3]) -> Self {
+        Self { x, y, z }
+    }
+
+    #[allow(dead_code)]
+    pub fn to_array(&self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
+
     pub fn distance(a: &Self, b: &Self) -> f64 {
         ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
     }
+
+    /// Direction from `a` to `b` projected on the x-z plane, in degrees from 0 to 360.
+    /// 0° points along +z, 90° along +x, 180° along -z and 270° along -x; y is ignored.
+    pub fn bearing(a: &Self, b: &Self) -> f64 {
+        (b.x - a.x).atan2(b.z - a.z).to_degrees().rem_euclid(360.0)
+    }
+}
+impl From<[f64; 3]> for Position {
+    fn from(coordinates: [f64; 3]) -> Self {
+        Self::from_array(coordinates)
+    }
+}
+impl From<Position> for [f64; 3] {
+    fn from(position: Position) -> Self {
+        position.to_array()
+    }
 }
 impl fmt::Display for Position {
     // This trait requires `fmt` with this exact signature.
@@ -39,6 +1047,19 @@ impl fmt::Display for Position {
     }
 }
 
+/// Relative tolerance of the distance comparisons. Routes whose lengths differ
+/// by less than this fraction are treated as equally long, so the solvers keep
+/// the first one found instead of flipping on floating point noise.
+const EPSILON: f64 = 1e-9;
+
+fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= EPSILON * a.abs().max(b.abs())
+}
+
+fn is_shorter(a: f64, b: f64) -> bool {
+    a < b && !approx_eq(a, b)
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default, Eq)]
 struct Planet {
     asteroid_belts: Option<Vec<i32>>,
@@ -47,22 +1068,52 @@ struct Planet {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
-struct AsteroidBelt {
+struct PlanetInfo {
     name: String,
+    planet_id: i32,
     position: Position,
     system_id: i32,
 }
-impl AsteroidBelt {
-    pub async fn load(id: &i32) -> anyhow::Result<Self> {
-        let url = format!("{ROOT}/universe/asteroid_belts/{id}/{PARAM}");
+impl PlanetInfo {
+    pub async fn load(esi: &Esi, id: &i32) -> anyhow::Result<Self> {
+        let url = esi.url(&format!("universe/planets/{id}"));
         debug!("url: {url}");
-        Ok(reqwest::get(url).await?.json::<Self>().await?)
+        esi.get(&url).await
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
-struct System {
-    constellation_id: i32,
+struct AsteroidBelt {
+    name: String,
+    position: Position,
+    system_id: i32,
+}
+impl AsteroidBelt {
+    pub async fn load(esi: &Esi, id: &i32) -> anyhow::Result<Self> {
+        let url = esi.url(&format!("universe/asteroid_belts/{id}"));
+        debug!("url: {url}");
+        esi.get(&url).await
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+struct Station {
+    name: String,
+    position: Position,
+    system_id: i32,
+}
+impl Station {
+    pub async fn load(esi: &Esi, id: &i32) -> anyhow::Result<Self> {
+        let url = esi.url(&format!("universe/stations/{id}"));
+        debug!("url: {url}");
+        esi.get(&url).await
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+struct System {
+    #[serde(default)]
+    constellation_id: i32,
     name: String,
     planets: Option<Vec<Planet>>,
     // position: Position,
@@ -71,13 +1122,56 @@ struct System {
     // star_id
     // stargates
     // stations
+    #[serde(default)]
     system_id: i32,
 }
+/// Where a system is, only the known space follows the belt naming of the ESI
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Space {
+    Known,
+    Wormhole,
+    Abyssal,
+}
+
 impl System {
-    pub async fn load(id: &i32) -> anyhow::Result<Self> {
-        let url = format!("{ROOT}/universe/systems/{id}/{PARAM}");
+    /// By the id ranges of the ESI, or by the `J123456` and Thera names of the wormholes
+    pub fn space(&self) -> Space {
+        let wormhole_name = self.name.len() == 7
+            && self.name.starts_with('J')
+            && self.name[1..].chars().all(|c| c.is_ascii_digit());
+        match self.system_id {
+            31000000..=31999999 => Space::Wormhole,
+            32000000..=32999999 => Space::Abyssal,
+            _ if wormhole_name || self.name == "Thera" => Space::Wormhole,
+            _ => Space::Known,
+        }
+    }
+
+    pub async fn load(esi: &Esi, id: &i32) -> anyhow::Result<Self> {
+        let system = Self::fetch(esi, id).await?;
+        system.validate(id)?;
+        Ok(system)
+    }
+
+    /// The system as the ESI has it, not validated
+    pub async fn fetch(esi: &Esi, id: &i32) -> anyhow::Result<Self> {
+        let url = esi.url(&format!("universe/systems/{id}"));
         debug!("url: {url}");
-        Ok(reqwest::get(url).await?.json::<Self>().await?)
+        esi.get(&url).await
+    }
+
+    /// Rejects the partial payloads instead of going on with the zero ids
+    fn validate(&self, id: &i32) -> anyhow::Result<()> {
+        if 0 == self.system_id {
+            anyhow::bail!("The system {id} has no system_id");
+        }
+        if *id != self.system_id {
+            anyhow::bail!("The system {id} came as the system {}", self.system_id);
+        }
+        if 0 == self.constellation_id {
+            anyhow::bail!("The system {id} has no constellation_id");
+        }
+        Ok(())
     }
 }
 
@@ -101,21 +1195,251 @@ struct Universe {
     systems: Option<Vec<Object>>,
 }
 impl Universe {
-    pub async fn load(names: &Vec<String>) -> anyhow::Result<Self> {
-        let url = format!("{ROOT}/universe/ids/{PARAM}");
+    pub async fn load(esi: &Esi, names: &Vec<String>) -> anyhow::Result<Self> {
+        let url = esi.url("universe/ids");
+        debug!("url: {url}");
+        esi.post(&url, names).await
+    }
+
+    fn categories(&self) -> Vec<(&str, &Option<Vec<Object>>)> {
+        vec![
+            ("agents", &self.agents),
+            ("alliances", &self.alliances),
+            ("characters", &self.characters),
+            ("constellations", &self.constellations),
+            ("corporations", &self.corporations),
+            ("factions", &self.factions),
+            ("inventory_types", &self.inventory_types),
+            ("regions", &self.regions),
+            ("stations", &self.stations),
+            ("systems", &self.systems),
+        ]
+    }
+}
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+struct Search {
+    solar_system: Option<Vec<i32>>,
+}
+impl Search {
+    /// Systems which names contain the query, the closest ones first
+    pub async fn systems(esi: &Esi, query: &str) -> anyhow::Result<Vec<Object>> {
+        let mut url = reqwest::Url::parse(&esi.url("search"))?;
+        url.query_pairs_mut()
+            .append_pair("categories", "solar_system")
+            .append_pair("search", query)
+            .append_pair("strict", "false");
         debug!("url: {url}");
-        let client = reqwest::Client::new();
-        Ok(client
-            .post(url)
-            .json(names)
-            .send()
-            .await?
-            .json::<Self>()
-            .await?)
+        let found: Self = esi.get(url.as_str()).await?;
+        let ids = found.solar_system.unwrap_or_default();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let names: Vec<Object> = esi.post(&esi.url("universe/names"), &ids).await?;
+        Ok(rank_candidates(query, names))
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
+/// Orders the names starting with the query first, then by the edit distance to it
+fn rank_candidates(query: &str, mut candidates: Vec<Object>) -> Vec<Object> {
+    let query = query.to_lowercase();
+    candidates.sort_by_cached_key(|obj| {
+        let name = obj.name.to_lowercase();
+        (
+            !name.starts_with(&query),
+            edit_distance(&query, &name),
+            name,
+        )
+    });
+    candidates
+}
+
+/// Levenshtein distance between the strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replace = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replace.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Asks to choose one of the candidates when there is a prompt to read the answer from,
+/// takes the closest one otherwise
+fn pick_candidate(
+    input: &str,
+    candidates: &[Object],
+    prompt: Option<&mut dyn std::io::BufRead>,
+) -> anyhow::Result<Option<i32>> {
+    let Some(best) = candidates.first() else {
+        return Ok(None);
+    };
+    let Some(prompt) = prompt else {
+        eprintln!("Using `{}` for `{input}`", best.name);
+        return Ok(Some(best.id));
+    };
+
+    eprintln!("`{input}` is not found, did you mean:");
+    for (n, obj) in candidates.iter().take(9).enumerate() {
+        eprintln!("{:>2}) {}", n + 1, obj.name);
+    }
+    eprint!("Choice [1]: ");
+    let mut line = String::new();
+    prompt.read_line(&mut line)?;
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(Some(best.id));
+    }
+    Ok(line
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| candidates.get(n.wrapping_sub(1)))
+        .map(|obj| obj.id))
+}
+
+impl fmt::Display for Universe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (category, objects) in self.categories() {
+            if let Some(objects) = objects {
+                writeln!(f, "{category}:")?;
+                for obj in objects {
+                    writeln!(f, "\t{} - {}", obj.id, obj.name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    Cloud,
+    Belt,
+    Other,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// The pattern of the place names like `{system} {cloud} - Asteroid Belt {belt}`,
+/// the fields other than {cloud} and {belt} match any text
+#[derive(Debug, PartialEq, Clone)]
+struct NameTemplate {
+    parts: Vec<Part>,
+}
+impl NameTemplate {
+    /// The cloud and the belt numbers of the name, None when the name doesn't match
+    pub fn parse(&self, name: &str) -> Option<(u32, u32)> {
+        let mut numbers = (0, 0);
+        Self::matches(&self.parts, name, &mut numbers).then_some(numbers)
+    }
+
+    /// The numbers of the name, an error when the name doesn't match
+    /// or the cloud number is out of the range of the planets
+    pub fn numbers(&self, name: &str) -> anyhow::Result<(u32, u32)> {
+        let Some((cloud_number, belt_number)) = self.parse(name) else {
+            anyhow::bail!("`{name}` doesn't match the name template");
+        };
+        if !(1..=CLOUD_NUMBER_LIMIT).contains(&cloud_number) {
+            anyhow::bail!(
+                "`{name}` has the cloud number {cloud_number} out of 1..={CLOUD_NUMBER_LIMIT}"
+            );
+        }
+        Ok((cloud_number, belt_number))
+    }
+
+    fn matches(parts: &[Part], name: &str, numbers: &mut (u32, u32)) -> bool {
+        match parts {
+            [] => name.is_empty(),
+            [Part::Text(text), rest @ ..] => name
+                .strip_prefix(text.as_str())
+                .is_some_and(|name| Self::matches(rest, name, numbers)),
+            [Part::Field(field), rest @ ..] => {
+                // A field ends where the following text starts, the earliest that fits wins
+                let ends = match rest.first() {
+                    Some(Part::Text(text)) => name
+                        .match_indices(text.as_str())
+                        .map(|(at, _)| at)
+                        .collect::<Vec<usize>>(),
+                    _ => vec![name.len()],
+                };
+                ends.into_iter().filter(|at| *at > 0).any(|at| {
+                    let value = &name[..at];
+                    let number = match field {
+                        Field::Cloud => value
+                            .parse::<Roman>()
+                            .map(|roman| *roman)
+                            .ok()
+                            .or_else(|| value.parse::<u32>().ok()),
+                        Field::Belt => value.parse::<u32>().ok(),
+                        Field::Other => Some(0),
+                    };
+                    let saved = *numbers;
+                    match (number, field) {
+                        (None, _) => return false,
+                        (Some(n), Field::Cloud) => numbers.0 = n,
+                        (Some(n), Field::Belt) => numbers.1 = n,
+                        (Some(_), Field::Other) => {}
+                    }
+                    let found = Self::matches(rest, &name[at..], numbers);
+                    if !found {
+                        *numbers = saved;
+                    }
+                    found
+                })
+            }
+        }
+    }
+}
+impl Default for NameTemplate {
+    fn default() -> Self {
+        NAME_TEMPLATE
+            .parse()
+            .expect("The default name template is valid")
+    }
+}
+impl std::str::FromStr for NameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = rest
+                        .find('}')
+                        .with_context(|| format!("Unclosed field in `{template}`"))?;
+                    if let Some(Part::Field(_)) = parts.last() {
+                        anyhow::bail!("The fields have to be separated by a text in `{template}`");
+                    }
+                    parts.push(Part::Field(match &rest[1..end] {
+                        "cloud" => Field::Cloud,
+                        "belt" => Field::Belt,
+                        _ => Field::Other,
+                    }));
+                    rest = &rest[end + 1..];
+                }
+                found => {
+                    let end = found.unwrap_or(rest.len());
+                    parts.push(Part::Text(rest[..end].to_string()));
+                    rest = &rest[end..];
+                }
+            }
+        }
+        Ok(Self { parts })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
 struct Place {
     id: i32,
     name: String,
@@ -124,330 +1448,7213 @@ struct Place {
     belt_number: u32,
 }
 impl Place {
-    pub fn new(id: &i32, name: &String, position: &Position) -> Self {
-        let tokens = name.trim().split_whitespace().collect::<Vec<&str>>();
-        assert_eq!(6, tokens.len());
+    /// A place numbered by the standard template, an error when the name doesn't match it
+    #[allow(dead_code)]
+    pub fn new(id: &i32, name: &str, position: &Position) -> anyhow::Result<Self> {
+        let (cloud_number, belt_number) = NameTemplate::default().numbers(name)?;
+        Ok(Self {
+            cloud_number,
+            belt_number,
+            ..Self::named(id, name, position)
+        })
+    }
+
+    /// A place numbered by the template, unnumbered when the name doesn't match it
+    pub fn parsed(id: &i32, name: &str, position: &Position, template: &NameTemplate) -> Self {
+        let (cloud_number, belt_number) = template.numbers(name).unwrap_or_else(|err| {
+            warn!("{err}");
+            (0, 0)
+        });
+        Self {
+            cloud_number,
+            belt_number,
+            ..Self::named(id, name, position)
+        }
+    }
 
+    /// A place numbered by the template, an error when the name doesn't match it
+    pub fn checked(
+        id: &i32,
+        name: &str,
+        position: &Position,
+        template: &NameTemplate,
+    ) -> anyhow::Result<Self> {
+        if !position.is_finite() {
+            anyhow::bail!("`{name}` ({id}) is at the broken position {position}");
+        }
+        let (cloud_number, belt_number) = template.numbers(name)?;
+        Ok(Self {
+            cloud_number,
+            belt_number,
+            ..Self::named(id, name, position)
+        })
+    }
+
+    /// A place which name doesn't follow the asteroid belt template
+    pub fn named(id: &i32, name: &str, position: &Position) -> Self {
         Self {
-            id: id.clone(),
-            name: name.clone(),
+            id: *id,
+            name: name.to_string(),
             position: position.clone(),
-            cloud_number: *tokens[1].parse::<Roman>().unwrap(),
-            belt_number: tokens[5].parse::<u32>().unwrap_or_default(),
+            cloud_number: 0,
+            belt_number: 0,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
 struct Cloud {
+    planet_id: i32,
     places: HashMap<i32, Place>,
+    // JSON keeps the integer keys of the maps as strings
     distances: HashMap<i32, HashMap<i32, f64>>,
 }
 impl Cloud {
     pub fn new() -> Self {
         Self {
+            planet_id: 0,
             places: HashMap::new(),
             distances: HashMap::new(),
         }
     }
 
-    pub fn get_name(&self, id: &i32) -> Option<String> {
-        if let Some(belt) = self.places.get(id) {
-            Some(belt.name.clone())
-        } else {
-            None
+    pub fn with_planet(planet_id: &i32) -> Self {
+        Self {
+            planet_id: *planet_id,
+            ..Self::new()
+        }
+    }
+
+    /// The name of the first belt in the ordinal order
+    pub fn name(&self) -> String {
+        self.get_ids_sorted_by_name()
+            .first()
+            .and_then(|id| self.get_name(id))
+            .unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.places.len()
+    }
+
+    /// Whether the cloud has more places than any solver routes quickly
+    pub fn is_slow(&self, threshold: usize) -> bool {
+        self.len() > threshold
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.places.is_empty()
+    }
+
+    /// Ids of the places in ascending order
+    #[allow(dead_code)]
+    pub fn ids(&self) -> Vec<i32> {
+        self.places.keys().cloned().sorted().collect()
+    }
+
+    /// Compares the places and the distances regardless of the order they were added in,
+    /// the coordinates and the distances are compared within the EPSILON
+    #[allow(dead_code)]
+    pub fn semantically_equals(&self, other: &Self) -> bool {
+        let same_position = |a: &Position, b: &Position| {
+            approx_eq(a.x, b.x) && approx_eq(a.y, b.y) && approx_eq(a.z, b.z)
+        };
+        self.planet_id == other.planet_id
+            && self.ids() == other.ids()
+            && self.places.values().all(|place| {
+                other.places.get(&place.id).is_some_and(|twin| {
+                    place.name == twin.name
+                        && place.cloud_number == twin.cloud_number
+                        && place.belt_number == twin.belt_number
+                        && same_position(&place.position, &twin.position)
+                })
+            })
+            && self.ids().iter().tuple_combinations().all(|(a, b)| {
+                match (self.distance_between(a, b), other.distance_between(a, b)) {
+                    (Some(lhs), Some(rhs)) => approx_eq(lhs, rhs),
+                    (lhs, rhs) => lhs == rhs,
+                }
+            })
+    }
+
+    pub fn get_name(&self, id: &i32) -> Option<String> {
+        self.places.get(id).map(|belt| belt.name.clone())
+    }
+
+    #[allow(dead_code)]
+    pub fn add(&mut self, id: &i32, name: &str, position: &Position) {
+        self.add_place(Place::parsed(id, name, position, &NameTemplate::default()));
+    }
+
+    /// Adds the place unless its position is broken
+    pub fn add_place(&mut self, place: Place) {
+        if !place.position.is_finite() {
+            warn!(
+                "Skipping `{}` ({}) at the broken position {}",
+                place.name, place.id, place.position
+            );
+            return;
+        }
+        let id = place.id;
+        // The new place gets the distances to all the others at once
+        let others = self.places.len();
+        self.distances
+            .entry(id)
+            .or_insert_with(|| HashMap::with_capacity(others));
+        for (destination, belt) in &self.places {
+            let distance = Position::distance(&place.position, &belt.position);
+            debug!(
+                "Distance between {} and {} - {}",
+                place.name, belt.name, distance
+            );
+
+            self.distances
+                .entry(id)
+                .or_default()
+                .insert(*destination, distance);
+
+            self.distances
+                .entry(*destination)
+                .or_default()
+                .insert(id, distance);
+        }
+
+        if let Some(old) = self.places.insert(id, place) {
+            warn!("The old value for {id} was replaced with: {:?}", old);
+        }
+    }
+
+    /// Adds the belts named by the standard template at once
+    #[allow(dead_code)]
+    pub fn add_many(&mut self, items: &[(i32, String, Position)]) {
+        let template = NameTemplate::default();
+        self.add_places(
+            items
+                .iter()
+                .map(|(id, name, position)| Place::parsed(id, name, position, &template))
+                .collect(),
+        );
+    }
+
+    /// Inserts all the places first and then computes the distances between all of them,
+    /// the places at the broken positions are skipped
+    pub fn add_places(&mut self, places: Vec<Place>) {
+        for anomaly in self.anomalies(&places) {
+            warn!("{anomaly}");
+        }
+        for place in places
+            .into_iter()
+            .filter(|place| place.position.is_finite())
+        {
+            self.places.insert(place.id, place);
+        }
+
+        self.distances.clear();
+        let others = self.places.len().saturating_sub(1);
+        for id in self.places.keys() {
+            self.distances.insert(*id, HashMap::with_capacity(others));
+        }
+        for (a, b) in self.ids().into_iter().tuple_combinations() {
+            let distance = Position::distance(&self.places[&a].position, &self.places[&b].position);
+            self.distances.entry(a).or_default().insert(b, distance);
+            self.distances.entry(b).or_default().insert(a, distance);
+        }
+        debug_assert!(self.is_symmetric(), "Asymmetric distances");
+    }
+
+    /// Whether every stored distance from a to b has the twin from b to a within
+    /// the EPSILON, the solvers take the distances in either direction
+    pub fn is_symmetric(&self) -> bool {
+        self.distances.iter().all(|(a, row)| {
+            row.iter().all(|(b, distance)| {
+                self.distances
+                    .get(b)
+                    .and_then(|twins| twins.get(a))
+                    .is_some_and(|twin| approx_eq(*distance, *twin))
+            })
+        })
+    }
+
+    /// Fails on the anomalies the places would bring into the cloud
+    pub fn check_places(&self, places: &[Place]) -> anyhow::Result<()> {
+        let anomalies = self.anomalies(places);
+        if !anomalies.is_empty() {
+            anyhow::bail!(anomalies.join("; "));
+        }
+        Ok(())
+    }
+
+    /// The replaced ids and the places sharing a position after the places are added
+    fn anomalies(&self, places: &[Place]) -> Vec<String> {
+        let mut anomalies = Vec::new();
+        let mut merged = self.places.clone();
+        for place in places {
+            if !place.position.is_finite() {
+                anomalies.push(format!(
+                    "{} is at the broken position {}",
+                    place.id, place.position
+                ));
+                continue;
+            }
+            if let Some(old) = merged.insert(place.id, place.clone()) {
+                anomalies.push(format!(
+                    "The old value for {} was replaced with: {:?}",
+                    old.id, old
+                ));
+            }
+        }
+        let mut ids = merged.keys().cloned().collect::<Vec<i32>>();
+        ids.sort();
+        for (a, b) in ids.into_iter().tuple_combinations() {
+            if merged[&a].position == merged[&b].position {
+                anomalies.push(format!("{a} and {b} are at the same position"));
+            }
+        }
+        anomalies
+    }
+
+    pub fn distance_between(&self, a: &i32, b: &i32) -> Option<f64> {
+        if let Some(value) = self.distances.get(a) {
+            return value.get(b).cloned();
+        }
+        None
+    }
+
+    /// The closest other place and the distance to it
+    #[allow(dead_code)]
+    pub fn nearest_neighbor_of(&self, id: &i32) -> Option<(i32, f64)> {
+        self.distances
+            .get(id)?
+            .iter()
+            .min_by(|(a, x), (b, y)| x.total_cmp(y).then(a.cmp(b)))
+            .map(|(id, distance)| (*id, *distance))
+    }
+
+    /// Up to `k` places closest to the position with the distances to them, the closest first
+    pub fn nearest_places_to(&self, position: &Position, k: usize) -> Vec<(i32, f64)> {
+        self.places
+            .values()
+            .map(|place| (place.id, Position::distance(position, &place.position)))
+            .sorted_by(|(a, x), (b, y)| x.total_cmp(y).then(a.cmp(b)))
+            .take(k)
+            .collect()
+    }
+
+    /// The two most distant places, the smaller id first
+    pub fn farthest_pair(&self) -> Option<(i32, i32, f64)> {
+        self.distances
+            .iter()
+            .flat_map(|(a, row)| row.iter().map(move |(b, distance)| (*a, *b, *distance)))
+            .filter(|(a, b, _)| a < b)
+            .max_by(|(a, b, x), (c, d, y)| x.total_cmp(y).then((c, d).cmp(&(a, b))))
+    }
+
+    /// The two closest places, the smaller id first
+    #[allow(dead_code)]
+    pub fn cheapest_edge(&self) -> Option<(i32, i32, f64)> {
+        self.distances
+            .iter()
+            .flat_map(|(a, row)| row.iter().map(move |(b, distance)| (*a, *b, *distance)))
+            .filter(|(a, b, _)| a < b)
+            .min_by(|(a, b, x), (c, d, y)| x.total_cmp(y).then((a, b).cmp(&(c, d))))
+    }
+
+    /// The longest link between two places, the same as the farthest pair
+    #[allow(dead_code)]
+    pub fn most_expensive_edge(&self) -> Option<(i32, i32, f64)> {
+        self.farthest_pair()
+    }
+
+    /// The length of the route over the distance between its ends, or over the diameter
+    /// of the cloud when the route returns to where it started. Close to 1 for the places
+    /// on a line, higher the more the route goes back and forth.
+    pub fn detour_ratio(&self, route: &[i32]) -> Option<f64> {
+        let (first, last) = (route.first()?, route.last()?);
+        let ends = self.distance_between(first, last).unwrap_or(0.0);
+        let line = if ends > EPSILON {
+            ends
+        } else {
+            self.farthest_pair()?.2
+        };
+        (line > EPSILON).then(|| self.route_distance(route) / line)
+    }
+
+    /// The summary of the distances between all the pairs of the places
+    pub fn distance_stats(&self) -> DistanceStats {
+        let mut distances = self
+            .distances
+            .iter()
+            .flat_map(|(a, others)| {
+                others
+                    .iter()
+                    .filter(move |(b, _)| a < *b)
+                    .map(|(_, distance)| *distance)
+            })
+            .collect::<Vec<f64>>();
+        if distances.is_empty() {
+            return DistanceStats::default();
+        }
+        distances.sort_by(f64::total_cmp);
+
+        let n = distances.len();
+        let median = if n % 2 == 1 {
+            distances[n / 2]
+        } else {
+            (distances[n / 2 - 1] + distances[n / 2]) / 2.0
+        };
+        DistanceStats {
+            min: distances[0],
+            max: distances[n - 1],
+            mean: distances.iter().sum::<f64>() / n as f64,
+            median,
+            diameter: self
+                .farthest_pair()
+                .map_or(0.0, |(_, _, distance)| distance),
+        }
+    }
+
+    /// The imported route if it is meant for this cloud: the ids of its places in their
+    /// order, the others are of the other clouds, or the tour of its instance.
+    /// Fails unless it visits every place once.
+    pub fn imported(&self, route: &ImportedRoute) -> Option<anyhow::Result<Vec<i32>>> {
+        let route = match route {
+            ImportedRoute::Ids(ids) if ids.iter().any(|id| self.places.contains_key(id)) => ids
+                .iter()
+                .filter(|id| self.places.contains_key(id))
+                .cloned()
+                .collect(),
+            ImportedRoute::Tour { planet_id, nodes }
+                if planet_id.is_none_or(|planet_id| planet_id == self.planet_id) =>
+            {
+                let ids = self.get_ids_sorted_by_name();
+                let mut route = Vec::new();
+                for node in nodes {
+                    match node.checked_sub(1).and_then(|n| ids.get(n)) {
+                        Some(id) => route.push(*id),
+                        None => {
+                            return Some(Err(anyhow::anyhow!(
+                                "The tour node {node} is not of the {} places",
+                                ids.len()
+                            )))
+                        }
+                    }
+                }
+                route
+            }
+            _ => return None,
+        };
+        Some(self.validate_route(&route).map(|_| route))
+    }
+
+    /// Fails unless the route visits every place of the cloud once
+    pub fn validate_route(&self, route: &[i32]) -> anyhow::Result<()> {
+        if let Some(id) = route.iter().find(|id| !self.places.contains_key(id)) {
+            anyhow::bail!(
+                "The belt {id} is not in the cloud of the planet {}",
+                self.planet_id
+            );
+        }
+        if let Some(id) = route.iter().duplicates().next() {
+            anyhow::bail!("The belt {id} is visited more than once");
+        }
+        if let Some(id) = self
+            .get_ids_sorted_by_name()
+            .iter()
+            .find(|id| !route.contains(id))
+        {
+            anyhow::bail!("The belt {id} is not visited");
+        }
+        Ok(())
+    }
+
+    /// The TSPLIB instance of the places at their coordinates in meters,
+    /// the node `n` is the `n`-th id of the comment in the ordinal order
+    pub fn to_tsplib(&self) -> String {
+        let ids = self.get_ids_sorted_by_name();
+        let mut lines = vec![
+            format!("NAME : planet_{}", self.planet_id),
+            format!("COMMENT : belts {}", ids.iter().join(" ")),
+            "TYPE : TSP".to_string(),
+            format!("DIMENSION : {}", ids.len()),
+            "EDGE_WEIGHT_TYPE : EUC_3D".to_string(),
+            "NODE_COORD_SECTION".to_string(),
+        ];
+        for (n, id) in ids.iter().enumerate() {
+            let position = &self.places[id].position;
+            lines.push(format!(
+                "{} {} {} {}",
+                n + 1,
+                position.x,
+                position.y,
+                position.z
+            ));
+        }
+        lines.push("EOF".to_string());
+        lines.join("\n") + "\n"
+    }
+
+    /// Splits the cloud into groups of places linked by the hops shorter than the threshold
+    pub fn split_by_gap(&self, threshold: f64) -> Vec<Cloud> {
+        let mut groups = Vec::new();
+        let mut visited = HashSet::new();
+        for start in self.get_ids_sorted_by_name() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let mut group = Cloud::with_planet(&self.planet_id);
+            let mut queue = vec![start];
+            while let Some(id) = queue.pop() {
+                if let Some(neighbors) = self.distances.get(&id) {
+                    for (neighbor, distance) in neighbors {
+                        if *distance < threshold && visited.insert(*neighbor) {
+                            queue.push(*neighbor);
+                        }
+                    }
+                }
+                group.add_place(self.places[&id].clone());
+            }
+            groups.push(group);
+        }
+        groups
+    }
+
+    /// The cloud of the places with the given ids only, the distances are copied.
+    /// Ids which are not in the cloud are skipped.
+    pub fn subcloud(&self, ids: &[i32]) -> Cloud {
+        let kept = ids
+            .iter()
+            .filter(|id| self.places.contains_key(id))
+            .cloned()
+            .collect::<HashSet<i32>>();
+        let mut cloud = Cloud::with_planet(&self.planet_id);
+        for id in &kept {
+            cloud.places.insert(*id, self.places[id].clone());
+            let distances = self.distances.get(id).map_or_else(HashMap::new, |all| {
+                all.iter()
+                    .filter(|(other, _)| kept.contains(other))
+                    .map(|(other, distance)| (*other, *distance))
+                    .collect()
+            });
+            cloud.distances.insert(*id, distances);
+        }
+        cloud
+    }
+
+    /// The cloud of the places of both clouds. The distances known to either
+    /// cloud are copied, only the ones between the clouds are computed.
+    #[allow(dead_code)]
+    pub fn merge(&self, other: &Cloud) -> Cloud {
+        let mut cloud = self.clone();
+        let others = self.places.len() + other.places.len();
+        for (id, place) in &other.places {
+            if let Some(old) = cloud.places.insert(*id, place.clone()) {
+                warn!("The old value for {id} was replaced with: {:?}", old);
+            }
+            let distances = cloud
+                .distances
+                .entry(*id)
+                .or_insert_with(|| HashMap::with_capacity(others));
+            if let Some(known) = other.distances.get(id) {
+                distances.extend(known);
+            }
+        }
+        for (a, place) in &self.places {
+            for (b, belt) in &other.places {
+                if a == b
+                    || cloud
+                        .distances
+                        .get(a)
+                        .is_some_and(|known| known.contains_key(b))
+                {
+                    continue;
+                }
+                let distance = Position::distance(&place.position, &belt.position);
+                cloud.distances.entry(*a).or_default().insert(*b, distance);
+                cloud.distances.entry(*b).or_default().insert(*a, distance);
+            }
+        }
+        cloud
+    }
+
+    /// The same places with the distances replaced by the cost of the hops
+    fn weighted(&self, cost: impl Fn(f64) -> f64) -> Cloud {
+        Cloud {
+            planet_id: self.planet_id,
+            places: self.places.clone(),
+            distances: self
+                .distances
+                .iter()
+                .map(|(id, others)| {
+                    let costs = others
+                        .iter()
+                        .map(|(other, distance)| (*other, cost(*distance)))
+                        .collect();
+                    (*id, costs)
+                })
+                .collect(),
+        }
+    }
+
+    /// The cloud of the places the hops objective visits: the fewest places picked greedily
+    /// every place is within the coverage of, the start and the through places among them.
+    /// None unless some places are covered by the others.
+    fn covering(&self, options: &RouteOptions) -> Option<Cloud> {
+        if options.objective != Objective::Hops {
+            return None;
+        }
+        let covers = |a: &i32, b: &i32| {
+            a == b
+                || self
+                    .distance_between(a, b)
+                    .is_some_and(|distance| distance <= options.coverage)
+        };
+        let ids = self.get_ids_sorted_by_name();
+        let mut kept = [options.start, options.through]
+            .into_iter()
+            .flatten()
+            .filter(|id| self.places.contains_key(id))
+            .unique()
+            .collect::<Vec<i32>>();
+        let mut uncovered = ids
+            .iter()
+            .filter(|id| !kept.iter().any(|visited| covers(visited, id)))
+            .cloned()
+            .collect::<Vec<i32>>();
+        while !uncovered.is_empty() {
+            let count = |id: &i32| uncovered.iter().filter(|other| covers(id, other)).count();
+            // The last of the reversed ids is the first by name of the ones covering the most
+            let Some(best) = ids.iter().rev().max_by_key(|id| count(id)).cloned() else {
+                break;
+            };
+            kept.push(best);
+            uncovered.retain(|id| !covers(&best, id));
+        }
+        (kept.len() < self.len()).then(|| self.subcloud(&kept))
+    }
+
+    /// The length of the route through the places with the given ids.
+    /// A hop to an id which is not in the cloud is infinitely long,
+    /// so such route is never taken for a short one.
+    pub fn route_distance(&self, route: &[i32]) -> f64 {
+        route
+            .iter()
+            .tuple_windows()
+            .map(|(a, b)| self.distance_between(a, b).unwrap_or(f64::INFINITY))
+            .sum()
+    }
+
+    /// The places in no particular order
+    #[allow(dead_code)]
+    pub fn iter_places(&self) -> impl Iterator<Item = (&i32, &Place)> {
+        self.places.iter()
+    }
+
+    /// The places by the cloud and the belt numbers of their names, then by the id
+    pub fn places_sorted(&self) -> Vec<&Place> {
+        let mut places = self.places.values().collect::<Vec<&Place>>();
+        places.sort_by(|a, b| {
+            if a.cloud_number == b.cloud_number {
+                a.belt_number.cmp(&b.belt_number).then(a.id.cmp(&b.id))
+            } else {
+                a.cloud_number.cmp(&b.cloud_number)
+            }
+        });
+        places
+    }
+
+    fn get_ids_sorted_by_name(&self) -> Vec<i32> {
+        self.places_sorted()
+            .into_iter()
+            .map(|belt| belt.id)
+            .collect::<Vec<i32>>()
+    }
+
+    pub fn get_ordinal_route(&self) -> (f64, Vec<i32>) {
+        let points = self.get_ids_sorted_by_name();
+        (self.route_distance(&points), points)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_best_route(&self) -> (f64, Vec<i32>) {
+        self.get_route(&Solver::default())
+    }
+
+    /// The best route with the names of the places, the id stands for a missing or empty name
+    #[allow(dead_code)]
+    pub fn best_route_named(&self) -> (f64, Vec<(i32, String)>) {
+        let (distance, route) = self.get_best_route();
+        let named = route
+            .into_iter()
+            .map(|id| {
+                let name = self.get_name(&id).filter(|name| !name.is_empty());
+                (id, name.unwrap_or_else(|| id.to_string()))
+            })
+            .collect();
+        (distance, named)
+    }
+
+    pub fn get_route(&self, solver: &Solver) -> (f64, Vec<i32>) {
+        self.solve(&RouteOptions {
+            solver: *solver,
+            ..RouteOptions::default()
+        })
+    }
+
+    pub fn solve(&self, options: &RouteOptions) -> (f64, Vec<i32>) {
+        debug_assert!(self.is_symmetric(), "Asymmetric distances");
+        let weighted = match options.objective {
+            Objective::Distance => None,
+            Objective::Time => Some(self.weighted(|distance| options.warp.hop_time(distance))),
+            Objective::Hops => self.covering(options),
+        };
+        if let Some(weighted) = weighted {
+            let (_, route) = weighted.solve(&RouteOptions {
+                objective: Objective::Distance,
+                ..options.clone()
+            });
+            return (self.route_distance(&route), route);
+        }
+        if let Some(polish) = options.polish {
+            let solved = self.solve(&RouteOptions {
+                polish: None,
+                ..options.clone()
+            });
+            let mut route = solved.1.clone();
+            let front = options
+                .start
+                .is_some_and(|id| route.first() == Some(&id))
+                .into();
+            let improved = match polish {
+                Polish::TwoOpt => self.two_opt(&mut route, front, options.deadline),
+                Polish::ThreeOpt => self.three_opt(&mut route, front, options.deadline),
+            };
+            // The polished route may bring the through place to an end
+            let ends = [route.first(), route.last()];
+            let through = options.through.filter(|_| route.len() > 2);
+            if !improved || through.is_some_and(|id| ends.contains(&Some(&id))) {
+                return solved;
+            }
+            return (self.route_distance(&route), route);
+        }
+        let start = options.start.filter(|id| self.places.contains_key(id));
+        let through = options
+            .through
+            .filter(|id| self.places.contains_key(id) && start != Some(*id));
+        if let Some(through) = through.filter(|_| self.len() > 2) {
+            let free = self.solve(&RouteOptions {
+                through: None,
+                ..options.clone()
+            });
+            let ends = [free.1.first(), free.1.last()];
+            if !ends.contains(&Some(&through)) {
+                return free;
+            }
+            let mut points = self.get_ids_sorted_by_name();
+            if let Some(start) = start {
+                points.retain(|id| *id != start);
+                points.insert(0, start);
+            }
+            return self.through_route(&points, start, through, options.deadline);
+        }
+        if self.is_slow(options.slow_threshold) {
+            warn!(
+                "The cloud of {} places is larger than {}, expect a delay \
+                 or limit it by --max-runtime",
+                self.len(),
+                options.slow_threshold
+            );
+        }
+
+        let mut points = self.get_ids_sorted_by_name();
+        if let Some(start) = start {
+            points.retain(|id| *id != start);
+            points.insert(0, start);
+        }
+
+        if points.is_empty() {
+            (0.0, vec![])
+        } else if 1 == points.len() {
+            (0.0, points.clone())
+        } else if 2 == points.len() {
+            (self.route_distance(&points), points.clone())
+        } else if !options.chain.is_empty() {
+            self.chain(&points, start, options).0
+        } else {
+            match options.solver {
+                Solver::Auto => match self.strategy(start.is_some(), options) {
+                    Strategy::Exact => self.exact(&points, start, options),
+                    Strategy::Greedy => {
+                        let (_, mut route) =
+                            self.lazzy_walker_hinted(&points, start, &options.hint);
+                        let front = if start.is_some() { 1 } else { 0 };
+                        self.two_opt(&mut route, front, options.deadline);
+                        (self.route_distance(&route), route)
+                    }
+                    Strategy::Clustered(gap) => self.clustered(gap, start, options),
+                },
+                Solver::Size if points.len() < options.brute_threshold => {
+                    self.exact(&points, start, options)
+                }
+                Solver::Size | Solver::NearestNeighbor => {
+                    self.lazzy_walker_hinted(&points, start, &options.hint)
+                }
+                Solver::NearestInsertion => self.nearest_insertion(&points, start),
+                Solver::FarthestInsertion => self.farthest_insertion(&points, start),
+                Solver::LinKernighan => self.lin_kernighan(&points, start, options.deadline),
+            }
+        }
+    }
+
+    /// Whether the solver proves the route of the options the shortest one
+    pub fn is_exact(&self, options: &RouteOptions) -> bool {
+        let n = self.len();
+        let pinned = options
+            .start
+            .is_some_and(|id| self.places.contains_key(&id));
+        let brute_force = n < options.brute_threshold
+            && permutations(n, pinned) <= options.permutation_limit
+            && options.deadline.is_none();
+        n <= 2
+            || options.through.is_none()
+                && options.objective == Objective::Distance
+                && (options.chain.is_empty()
+                    && matches!(options.solver, Solver::Auto | Solver::Size)
+                    || options.chain.first() == Some(&Stage::Exact))
+                && brute_force
+    }
+
+    /// The route of the chain of the options and its stages which ran. The stages
+    /// after the deadline are skipped and the nearest neighbor routes the cloud when
+    /// no stage builds its route.
+    fn chain(
+        &self,
+        points: &[i32],
+        start: Option<i32>,
+        options: &RouteOptions,
+    ) -> ((f64, Vec<i32>), Vec<Stage>) {
+        let pinned = start.is_some();
+        let fits = points.len() < options.brute_threshold
+            && permutations(points.len(), pinned) <= options.permutation_limit;
+        let front = pinned.into();
+        let mut route: Option<Vec<i32>> = None;
+        let mut ran = Vec::new();
+        for stage in &options.chain {
+            if options
+                .deadline
+                .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+            {
+                break;
+            }
+            match (stage, route.as_mut()) {
+                (Stage::Exact, None) if fits => {
+                    route = Some(self.brute_force(points, start, options.deadline).1);
+                    ran.push(*stage);
+                    break;
+                }
+                (Stage::NearestNeighbor, None) => {
+                    route = Some(self.lazzy_walker_hinted(points, start, &options.hint).1)
+                }
+                (Stage::NearestInsertion, None) => {
+                    route = Some(self.nearest_insertion(points, start).1)
+                }
+                (Stage::FarthestInsertion, None) => {
+                    route = Some(self.farthest_insertion(points, start).1)
+                }
+                (Stage::LinKernighan, None) => {
+                    route = Some(self.lin_kernighan(points, start, options.deadline).1)
+                }
+                (Stage::TwoOpt, Some(route)) => {
+                    self.two_opt(route, front, options.deadline);
+                }
+                (Stage::ThreeOpt, Some(route)) => {
+                    self.three_opt(route, front, options.deadline);
+                }
+                _ => continue,
+            }
+            ran.push(*stage);
+        }
+        let route =
+            route.unwrap_or_else(|| self.lazzy_walker_hinted(points, start, &options.hint).1);
+        ((self.route_distance(&route), route), ran)
+    }
+
+    /// The minimum spanning tree of the places by Prim's algorithm: the edges
+    /// from the tree to the place added to it, grown from the smallest id
+    pub fn mst(&self) -> Vec<(i32, i32, f64)> {
+        let ids = self.ids();
+        let Some((first, rest)) = ids.split_first() else {
+            return vec![];
+        };
+        // The place of the tree closest to every place outside of it
+        let mut outside = rest
+            .iter()
+            .map(|id| (*id, *first, self.distance_between(first, id).unwrap_or(0.0)))
+            .collect::<Vec<(i32, i32, f64)>>();
+        let mut edges = Vec::with_capacity(rest.len());
+        while let Some(closest) = outside
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.2.total_cmp(&b.2).then(a.0.cmp(&b.0)))
+            .map(|(index, _)| index)
+        {
+            let (added, from, distance) = outside.swap_remove(closest);
+            edges.push((from, added, distance));
+            for (id, nearest, to_tree) in outside.iter_mut() {
+                let distance = self.distance_between(&added, id).unwrap_or(0.0);
+                if distance < *to_tree {
+                    (*nearest, *to_tree) = (added, distance);
+                }
+            }
+        }
+        edges
+    }
+
+    /// The weight of the minimum spanning tree. Every route is a spanning tree,
+    /// so none is shorter than this.
+    pub fn lower_bound(&self) -> f64 {
+        self.mst().iter().map(|(_, _, distance)| distance).sum()
+    }
+
+    /// The brute force unless it has too many routes to measure
+    fn exact(&self, points: &[i32], start: Option<i32>, options: &RouteOptions) -> (f64, Vec<i32>) {
+        let count = permutations(points.len(), start.is_some());
+        if count > options.permutation_limit {
+            warn!(
+                "{count} routes of {} places exceed the limit of {}, \
+                 falling back to the nearest neighbor",
+                points.len(),
+                options.permutation_limit
+            );
+            self.lazzy_walker(points, start)
+        } else if options.deadline.is_some() {
+            // The nearest neighbor is at hand if the brute force runs out of time
+            let greedy = self.lazzy_walker(points, start);
+            let exact = self.brute_force(points, start, options.deadline);
+            if is_shorter(exact.0, greedy.0) {
+                exact
+            } else {
+                greedy
+            }
+        } else {
+            self.brute_force(points, start, None)
+        }
+    }
+
+    /// The choice of the auto solver:
+    /// - the brute force for the clouds smaller than the brute threshold
+    ///   which don't exceed the permutation limit,
+    /// - the clusters one by one when the cloud splits by the gap of `CLUSTER_GAP`
+    ///   times the median distance from a place to its nearest neighbor,
+    /// - the nearest neighbor improved by the 2-opt otherwise.
+    fn strategy(&self, pinned: bool, options: &RouteOptions) -> Strategy {
+        let n = self.len();
+        if n < options.brute_threshold && permutations(n, pinned) <= options.permutation_limit {
+            return Strategy::Exact;
+        }
+        let mut nearest = self
+            .ids()
+            .iter()
+            .filter_map(|id| self.nearest_neighbor_of(id))
+            .map(|(_, distance)| distance)
+            .collect::<Vec<f64>>();
+        nearest.sort_by(f64::total_cmp);
+        let gap = nearest
+            .get(nearest.len() / 2)
+            .map_or(0.0, |median| median * CLUSTER_GAP);
+        if gap > 0.0 && self.split_by_gap(gap).len() > 1 {
+            Strategy::Clustered(gap)
+        } else {
+            Strategy::Greedy
+        }
+    }
+
+    /// Routes the groups split by the gap one by one: every next group is the one
+    /// closest to the end of the route so far and is entered at its closest place.
+    /// The joined route is improved by the 2-opt.
+    fn clustered(&self, gap: f64, start: Option<i32>, options: &RouteOptions) -> (f64, Vec<i32>) {
+        let mut rest = self.split_by_gap(gap);
+        let first = start
+            .and_then(|start| {
+                rest.iter()
+                    .position(|group| group.places.contains_key(&start))
+            })
+            .unwrap_or(0);
+        let mut group = rest.remove(first);
+        let mut entry = start;
+        let mut route = Vec::with_capacity(self.len());
+        loop {
+            let inner = RouteOptions {
+                start: entry,
+                through: None,
+                ..options.clone()
+            };
+            route.extend(group.solve(&inner).1);
+            let Some(last) = route.last() else { break };
+            let closest = rest
+                .iter()
+                .enumerate()
+                .flat_map(|(i, group)| group.ids().into_iter().map(move |id| (i, id)))
+                .min_by(|(_, a), (_, b)| {
+                    let distance = |id: &i32| self.distance_between(last, id).unwrap_or(f64::MAX);
+                    distance(a).total_cmp(&distance(b)).then(a.cmp(b))
+                });
+            match closest {
+                Some((i, id)) => {
+                    group = rest.remove(i);
+                    entry = Some(id);
+                }
+                None => break,
+            }
+        }
+
+        let front = if start.is_some() { 1 } else { 0 };
+        self.two_opt(&mut route, front, options.deadline);
+        (self.route_distance(&route), route)
+    }
+
+    /// The mean position of the places
+    pub fn centroid(&self) -> Option<Position> {
+        let n = self.places.len() as f64;
+        let sum = self
+            .places
+            .values()
+            .fold((0.0, 0.0, 0.0), |(x, y, z), place| {
+                (
+                    x + place.position.x,
+                    y + place.position.y,
+                    z + place.position.z,
+                )
+            });
+        (n > 0.0).then(|| Position::new(&(sum.0 / n), &(sum.1 / n), &(sum.2 / n)))
+    }
+
+    /// The place closest to the centroid
+    pub fn nearest_to_centroid(&self) -> Option<i32> {
+        let centroid = self.centroid()?;
+        self.nearest_places_to(&centroid, 1)
+            .first()
+            .map(|(id, _)| *id)
+    }
+
+    /// Finds a place by its id or name, or the nearest place to the `x,y,z` coordinates
+    pub fn find(&self, key: &str) -> Option<i32> {
+        let coordinates = key
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>();
+        match (key.parse::<i32>(), coordinates.as_deref()) {
+            (Ok(id), _) if self.places.contains_key(&id) => Some(id),
+            (_, Ok([x, y, z])) => self
+                .nearest_places_to(&Position::new(x, y, z), 1)
+                .first()
+                .map(|(id, _)| *id),
+            _ => self
+                .places
+                .values()
+                .find(|place| place.name.eq_ignore_ascii_case(key.trim()))
+                .map(|place| place.id),
+        }
+    }
+
+    /// The length of the open route and of the warp back from its last place to the first
+    pub fn route_with_return_total(&self, route: &[i32]) -> (f64, f64) {
+        let back = match (route.first(), route.last()) {
+            (Some(first), Some(last)) if route.len() > 1 => self.route_distance(&[*last, *first]),
+            _ => 0.0,
+        };
+        (self.route_distance(route), back)
+    }
+
+    /// The warp from the last place of the route to the station
+    pub fn station_leg(&self, route: &[i32], station: &Position) -> f64 {
+        route
+            .last()
+            .and_then(|last| self.places.get(last))
+            .map_or(0.0, |place| Position::distance(&place.position, station))
+    }
+
+    /// Positions of the places along the route.
+    /// Ids which are not in the cloud are skipped.
+    #[allow(dead_code)]
+    pub fn route_coordinates(&self, route: &[i32]) -> Vec<Position> {
+        route
+            .iter()
+            .filter_map(|id| self.places.get(id))
+            .map(|place| place.position.clone())
+            .collect()
+    }
+
+    /// Checks the route against the exact optimum.
+    /// Returns None when the cloud is too large to be brute forced.
+    #[allow(dead_code)]
+    pub fn route_is_optimal(&self, route: &[i32]) -> Option<bool> {
+        if self.len() >= BRUTE_FORCE_LIMIT {
+            return None;
+        }
+        let (optimal, _) = self.get_route(&Solver::Size);
+        Some(approx_eq(self.route_distance(route), optimal))
+    }
+
+    fn lazzy_walker(&self, points: &[i32], start: Option<i32>) -> (f64, Vec<i32>) {
+        self.lazzy_walker_hinted(points, start, &[])
+    }
+
+    /// The nearest neighbor which breaks the ties by the order of the hint,
+    /// the places out of it come after the hinted ones
+    fn lazzy_walker_hinted(
+        &self,
+        points: &[i32],
+        start: Option<i32>,
+        hint: &[i32],
+    ) -> (f64, Vec<i32>) {
+        let rank = |id: &i32| {
+            hint.iter()
+                .position(|hinted| hinted == id)
+                .unwrap_or(hint.len())
+        };
+        if let Some(start) = start {
+            let tail = points.iter().filter(|id| **id != start).cloned().collect();
+            return self.lazzy_walker_impl(Self::route_from(start, points.len()), tail, &rank);
+        }
+
+        let mut starts = LinkedList::new();
+        for point in points.iter().sorted_by_key(|id| rank(id)) {
+            starts.push_back(point);
+        }
+
+        let mut min_dist = f64::MAX;
+        let mut min_route = Vec::new();
+        let mut count = points.len();
+        while count > 0 {
+            if let Some(point) = starts.pop_front() {
+                let tail = starts.iter().cloned().cloned().collect::<Vec<i32>>();
+                let route = Self::route_from(*point, points.len());
+                let (dist, route) = self.lazzy_walker_impl(route, tail, &rank);
+                if is_shorter(dist, min_dist) {
+                    min_dist = dist;
+                    min_route = route;
+                }
+                starts.push_back(point);
+            }
+
+            count -= 1;
+        }
+
+        (min_dist, min_route)
+    }
+
+    /// The route of the start only, with the room for all the places
+    fn route_from(start: i32, capacity: usize) -> Vec<i32> {
+        let mut route = Vec::with_capacity(capacity);
+        route.push(start);
+        route
+    }
+
+    fn lazzy_walker_impl(
+        &self,
+        mut route: Vec<i32>,
+        mut points: Vec<i32>,
+        rank: &impl Fn(&i32) -> usize,
+    ) -> (f64, Vec<i32>) {
+        if points.is_empty() {
+            return (self.route_distance(&route), route);
+        }
+
+        if let Some(point) = route.iter().last() {
+            // The closest and the first by the rank of them goes last
+            points.sort_by(|a, b| {
+                let d_a = self.distance_between(point, a).unwrap();
+                let d_b = self.distance_between(point, b).unwrap();
+                d_b.total_cmp(&d_a).then(rank(b).cmp(&rank(a)))
+            });
+            if let Some(closest) = points.pop() {
+                route.push(closest);
+            }
+        }
+        self.lazzy_walker_impl(route, points, rank)
+    }
+
+    /// Exhaustive search, the routes starting from every point are searched in parallel.
+    /// A pinned start is expected to be the first of the points.
+    /// The search of every head stops at the deadline with the best route found so far.
+    fn brute_force(
+        &self,
+        points: &[i32],
+        start: Option<i32>,
+        deadline: Option<std::time::Instant>,
+    ) -> (f64, Vec<i32>) {
+        let heads = if start.is_some() {
+            vec![0]
+        } else {
+            (0..points.len()).collect::<Vec<usize>>()
+        };
+
+        let best = heads
+            .par_iter()
+            .map(|head| {
+                let mut minimal = f64::MAX;
+                let mut route = Vec::with_capacity(points.len());
+                // The same buffer is refilled by every permutation
+                let mut path = Vec::with_capacity(points.len());
+                // The tails are permuted in place in the lexicographic order
+                let mut tail = (0..points.len())
+                    .filter(|i| i != head)
+                    .collect::<Vec<usize>>();
+                for n in 0.. {
+                    if n % 4096 == 4095 && expired(deadline) {
+                        break;
+                    }
+                    // The reversed route is as long as the route, so only one of them is measured,
+                    // but a pinned start breaks the symmetry
+                    if start.is_some() || tail.last().is_none_or(|last| last > head) {
+                        path.clear();
+                        path.extend(std::iter::once(head).chain(&tail).map(|i| points[*i]));
+                        let distance = self.route_distance(&path);
+                        if is_shorter(distance, minimal) {
+                            minimal = distance;
+                            route.clone_from(&path);
+                        }
+                    }
+                    if !next_permutation(&mut tail) {
+                        break;
+                    }
+                }
+                (minimal, route)
+            })
+            .collect::<Vec<(f64, Vec<i32>)>>();
+
+        best.into_iter()
+            .reduce(|best, other| {
+                if is_shorter(other.0, best.0) {
+                    other
+                } else {
+                    best
+                }
+            })
+            .unwrap_or((0.0, vec![]))
+    }
+
+    fn nearest_insertion(&self, points: &[i32], start: Option<i32>) -> (f64, Vec<i32>) {
+        let distance = |a: &i32, b: &i32| self.distance_between(a, b).unwrap_or(0.0);
+
+        // Start with the closest pair of belts
+        let (first, second) = points
+            .iter()
+            .tuple_combinations()
+            .filter(|(a, _)| start.is_none() || start == Some(**a))
+            .min_by(|(a, b), (c, d)| distance(a, b).total_cmp(&distance(c, d)))
+            .unwrap();
+        // Nothing is inserted in front of the pinned start
+        let front = if start.is_some() { 1 } else { 0 };
+        let mut route = vec![*first, *second];
+        let mut rest = points
+            .iter()
+            .filter(|id| *id != first && *id != second)
+            .cloned()
+            .collect::<Vec<i32>>();
+
+        while !rest.is_empty() {
+            let mut cheapest = (f64::MAX, 0, 0);
+            for (i, point) in rest.iter().enumerate() {
+                let (increase, position) = self.cheapest_insertion(&route, point, front);
+                if is_shorter(increase, cheapest.0) {
+                    cheapest = (increase, i, position);
+                }
+            }
+            let (_, i, position) = cheapest;
+            route.insert(position, rest.remove(i));
+        }
+
+        (self.route_distance(&route), route)
+    }
+
+    fn farthest_insertion(&self, points: &[i32], start: Option<i32>) -> (f64, Vec<i32>) {
+        let distance = |a: &i32, b: &i32| self.distance_between(a, b).unwrap_or(0.0);
+
+        let (first, second) = match start {
+            Some(start) => {
+                let farthest = points
+                    .iter()
+                    .filter(|id| **id != start)
+                    .max_by(|a, b| distance(&start, a).total_cmp(&distance(&start, b)))
+                    .unwrap();
+                (start, *farthest)
+            }
+            None => self
+                .farthest_pair()
+                .map(|(a, b, _)| (a, b))
+                .unwrap_or((points[0], points[1])),
+        };
+        let front = if start.is_some() { 1 } else { 0 };
+        let mut route = vec![first, second];
+        let mut rest = points
+            .iter()
+            .filter(|id| **id != first && **id != second)
+            .cloned()
+            .collect::<Vec<i32>>();
+
+        while !rest.is_empty() {
+            // The belt with the largest distance to its closest belt of the route
+            let gap = |point: &i32| {
+                route
+                    .iter()
+                    .map(|id| distance(id, point))
+                    .fold(f64::MAX, f64::min)
+            };
+            let (i, _) = rest
+                .iter()
+                .enumerate()
+                .max_by(|(i, a), (j, b)| gap(a).total_cmp(&gap(b)).then(j.cmp(i)))
+                .unwrap();
+            let point = rest.remove(i);
+            let (_, position) = self.cheapest_insertion(&route, &point, front);
+            route.insert(position, point);
+        }
+
+        (self.route_distance(&route), route)
+    }
+
+    /// Simplified Lin-Kernighan over the 2-opt local optimum.
+    /// A dummy place at zero distance from the others closes the route into a tour,
+    /// a pinned start is kept next to the dummy by the penalty on the other links.
+    fn lin_kernighan(
+        &self,
+        points: &[i32],
+        start: Option<i32>,
+        deadline: Option<std::time::Instant>,
+    ) -> (f64, Vec<i32>) {
+        let (_, mut route) = self.lazzy_walker(points, start);
+        let front = if start.is_some() { 1 } else { 0 };
+        self.two_opt(&mut route, front, deadline);
+
+        let n = route.len();
+        let penalty = if start.is_some() {
+            2.0 * self.route_distance(&route) + 1.0
+        } else {
+            0.0
+        };
+        let dist = (0..=n)
+            .map(|a| {
+                (0..=n)
+                    .map(|b| match (a == n, b == n) {
+                        (true, true) => 0.0,
+                        (true, false) if b == 0 => 0.0,
+                        (false, true) if a == 0 => 0.0,
+                        (true, false) | (false, true) => penalty,
+                        _ => self.distance_between(&route[a], &route[b]).unwrap_or(0.0),
+                    })
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<Vec<f64>>>();
+
+        let mut tour = (0..=n).collect::<Vec<usize>>();
+        while !expired(deadline) && Self::lk_pass(&dist, &mut tour) {}
+
+        // Cut the tour at the dummy, the pinned start follows it
+        let at = tour.iter().position(|i| *i == n).unwrap();
+        tour.rotate_left(at);
+        let mut path = tour[1..].iter().map(|i| route[*i]).collect::<Vec<i32>>();
+        if start.is_some_and(|start| path.last() == Some(&start)) {
+            path.reverse();
+        }
+        (self.route_distance(&path), path)
+    }
+
+    /// One round of the variable depth moves from every edge of the tour.
+    /// The edge (t1, t2) is dropped, then the chain links t2 to some t3 and drops
+    /// the edge (t4, t3) before it, so t4 becomes the new t2. The chain goes on
+    /// while its gain is positive and the best closed tour of the chain is kept.
+    fn lk_pass(dist: &[Vec<f64>], tour: &mut Vec<usize>) -> bool {
+        let m = tour.len();
+        let length = |tour: &[usize]| {
+            (0..m)
+                .map(|i| dist[tour[i]][tour[(i + 1) % m]])
+                .sum::<f64>()
+        };
+        let mut improved = false;
+        for t1 in 0..m {
+            for reversed in [false, true] {
+                let mut path = tour.clone();
+                if reversed {
+                    path.reverse();
+                }
+                // t1 is kept first, so t2 is the second
+                let at = path.iter().position(|i| *i == t1).unwrap();
+                path.rotate_left(at);
+
+                let before = length(&path);
+                let mut gain = dist[path[0]][path[1]];
+                let mut best = (0.0, None);
+                let mut used = HashSet::from([path[1]]);
+                loop {
+                    let t2 = path[1];
+                    let next = (3..m)
+                        .filter(|i| !used.contains(&path[i - 1]))
+                        .filter(|i| gain - dist[t2][path[*i]] > 0.0)
+                        .max_by(|i, j| {
+                            let by = |k: &usize| dist[path[k - 1]][path[*k]] - dist[t2][path[*k]];
+                            by(i).total_cmp(&by(j)).then(j.cmp(i))
+                        });
+                    let Some(i) = next else {
+                        break;
+                    };
+                    gain += dist[path[i - 1]][path[i]] - dist[t2][path[i]];
+                    path[1..i].reverse();
+                    used.insert(path[1]);
+                    let closed = gain - dist[path[1]][path[0]];
+                    if is_shorter(-closed, -best.0) {
+                        best = (closed, Some(path.clone()));
+                    }
+                }
+
+                if let (_, Some(path)) = best {
+                    if is_shorter(length(&path), before) {
+                        *tour = path;
+                        improved = true;
+                    }
+                }
+            }
+        }
+        improved
+    }
+
+    /// Improves the existing route by the 2-opt and or-opt passes
+    /// until neither of them makes it shorter, returns the new length
+    #[allow(dead_code)]
+    pub fn optimize_in_place(&self, route: &mut Vec<i32>) -> f64 {
+        while self.two_opt(route, 0, None) | self.or_opt(route, 0) {}
+        self.route_distance(route)
+    }
+
+    /// Moves the segments of up to three places, as they are or reversed,
+    /// to the position where the route becomes shorter,
+    /// nothing before the `front` position is moved
+    fn or_opt(&self, route: &mut Vec<i32>, front: usize) -> bool {
+        let distance = |a: Option<&i32>, b: Option<&i32>| match (a, b) {
+            (Some(a), Some(b)) => self.distance_between(a, b).unwrap_or(0.0),
+            _ => 0.0,
+        };
+        let mut improved = false;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for len in 1..=3 {
+                let mut i = front;
+                while i + len <= route.len() && len < route.len() {
+                    let segment = route[i..i + len].to_vec();
+                    let (first, last) = (segment.first(), segment.last());
+                    let (prev, next) = (i.checked_sub(1).map(|p| &route[p]), route.get(i + len));
+                    let removal =
+                        distance(prev, first) + distance(last, next) - distance(prev, next);
+
+                    let mut rest = route.clone();
+                    rest.drain(i..i + len);
+                    let mut best = (removal, None);
+                    for j in front..=rest.len() {
+                        if j == i {
+                            continue;
+                        }
+                        let (p, q) = (j.checked_sub(1).map(|p| &rest[p]), rest.get(j));
+                        let base = distance(p, q);
+                        for reversed in [false, true] {
+                            let (head, tail) = if reversed {
+                                (last, first)
+                            } else {
+                                (first, last)
+                            };
+                            let insertion = distance(p, head) + distance(tail, q) - base;
+                            if is_shorter(insertion, best.0) {
+                                best = (insertion, Some((j, reversed)));
+                            }
+                        }
+                    }
+
+                    if let (_, Some((j, reversed))) = best {
+                        let mut segment = segment;
+                        if reversed {
+                            segment.reverse();
+                        }
+                        rest.splice(j..j, segment);
+                        *route = rest;
+                        changed = true;
+                        improved = true;
+                    }
+                    i += 1;
+                }
+            }
+        }
+        improved
+    }
+
+    /// Cuts the route into the segments `..i`, `i..j`, `j..k` and `k..` and reconnects
+    /// the middle two by the best of the seven ways: either of them or both reversed,
+    /// swapped as they are or with either of them reversed. The best improving move
+    /// is applied every round until none is left, nothing before the `front` position
+    /// is moved, no new round starts after the deadline.
+    fn three_opt(
+        &self,
+        route: &mut Vec<i32>,
+        front: usize,
+        deadline: Option<std::time::Instant>,
+    ) -> bool {
+        let distance = |a: Option<&i32>, b: Option<&i32>| match (a, b) {
+            (Some(a), Some(b)) => self.distance_between(a, b).unwrap_or(0.0),
+            _ => 0.0,
+        };
+        let n = route.len();
+        let mut improved = false;
+        while !expired(deadline) {
+            let mut best = (0.0, None);
+            for i in front..n {
+                let before = i.checked_sub(1).map(|p| &route[p]);
+                for j in i + 1..n {
+                    for k in j + 1..=n {
+                        let (b, c) = (Some(&route[i]), Some(&route[j - 1]));
+                        let (d, e) = (Some(&route[j]), Some(&route[k - 1]));
+                        let after = route.get(k);
+                        let removed = distance(before, b) + distance(c, d) + distance(e, after);
+                        // The ends of the first and the second segment of the reconnection
+                        let moves = [
+                            ((c, b), (d, e)),
+                            ((b, c), (e, d)),
+                            ((c, b), (e, d)),
+                            ((d, e), (b, c)),
+                            ((d, e), (c, b)),
+                            ((e, d), (b, c)),
+                            ((e, d), (c, b)),
+                        ];
+                        for (case, ((x0, x1), (y0, y1))) in moves.into_iter().enumerate() {
+                            let added =
+                                distance(before, x0) + distance(x1, y0) + distance(y1, after);
+                            let gain = removed - added;
+                            if is_shorter(-gain, -best.0) && is_shorter(added, removed) {
+                                best = (gain, Some((i, j, k, case)));
+                            }
+                        }
+                    }
+                }
+            }
+            let (_, Some((i, j, k, case))) = best else {
+                break;
+            };
+            let (mut first, mut second) = (route[i..j].to_vec(), route[j..k].to_vec());
+            if [0, 2, 4, 6].contains(&case) {
+                first.reverse();
+            }
+            if [1, 2, 5, 6].contains(&case) {
+                second.reverse();
+            }
+            let middle = if case < 3 {
+                [first, second].concat()
+            } else {
+                [second, first].concat()
+            };
+            route.splice(i..k, middle);
+            improved = true;
+        }
+        improved
+    }
+
+    /// Reverses the segments of the route while it becomes shorter,
+    /// nothing before the `front` position is moved, no new round starts after the deadline
+    fn two_opt(
+        &self,
+        route: &mut [i32],
+        front: usize,
+        deadline: Option<std::time::Instant>,
+    ) -> bool {
+        self.two_opt_with(route, front, deadline, |_, _, _| true)
+    }
+
+    /// The 2-opt which reverses only the segments `i..=j` the `allowed` agrees to
+    fn two_opt_with(
+        &self,
+        route: &mut [i32],
+        front: usize,
+        deadline: Option<std::time::Instant>,
+        allowed: impl Fn(&[i32], usize, usize) -> bool,
+    ) -> bool {
+        let distance = |a: &i32, b: &i32| self.distance_between(a, b).unwrap_or(0.0);
+        let mut improved = false;
+        let mut changed = true;
+        while changed && !expired(deadline) {
+            changed = false;
+            for i in front..route.len() {
+                for j in i + 1..route.len() {
+                    let (mut before, mut after) = (0.0, 0.0);
+                    if i > 0 {
+                        before += distance(&route[i - 1], &route[i]);
+                        after += distance(&route[i - 1], &route[j]);
+                    }
+                    if j + 1 < route.len() {
+                        before += distance(&route[j], &route[j + 1]);
+                        after += distance(&route[i], &route[j + 1]);
+                    }
+                    if is_shorter(after, before) && allowed(route, i, j) {
+                        route[i..=j].reverse();
+                        changed = true;
+                        improved = true;
+                    }
+                }
+            }
+        }
+        improved
+    }
+
+    /// The nearest neighbor route improved by the 2-opt keeping the `through` place
+    /// off the ends: it is moved inside when the walker ends at it
+    fn through_route(
+        &self,
+        points: &[i32],
+        start: Option<i32>,
+        through: i32,
+        deadline: Option<std::time::Instant>,
+    ) -> (f64, Vec<i32>) {
+        let distance = |a: &i32, b: &i32| self.distance_between(a, b).unwrap_or(0.0);
+        let (_, mut route) = self.lazzy_walker(points, start);
+        if route.first() == Some(&through) || route.last() == Some(&through) {
+            route.retain(|id| *id != through);
+            let position = (1..route.len())
+                .min_by(|a, b| {
+                    let increase = |p: &usize| {
+                        distance(&route[p - 1], &through) + distance(&through, &route[*p])
+                            - distance(&route[p - 1], &route[*p])
+                    };
+                    increase(a).total_cmp(&increase(b))
+                })
+                .unwrap_or(1);
+            route.insert(position, through);
+        }
+
+        let front = if start.is_some() { 1 } else { 0 };
+        self.two_opt_with(&mut route, front, deadline, |route, i, j| {
+            // The reversal brings route[j] to the front and route[i] to the back
+            !(i == 0 && route[j] == through || j + 1 == route.len() && route[i] == through)
+        });
+        (self.route_distance(&route), route)
+    }
+
+    /// Inserts the place where it lengthens the route the least, returns the new length.
+    /// An id which is not in the cloud is not inserted.
+    #[allow(dead_code)]
+    pub fn insert_best_position(&self, route: &mut Vec<i32>, id: i32) -> f64 {
+        if self.places.contains_key(&id) {
+            if route.is_empty() {
+                route.push(id);
+            } else {
+                let (_, position) = self.cheapest_insertion(route, &id, 0);
+                route.insert(position, id);
+            }
+        }
+        self.route_distance(route)
+    }
+
+    /// The smallest increase of the route length by inserting the point
+    /// and the position of that insertion, never before the `front` position
+    fn cheapest_insertion(&self, route: &[i32], point: &i32, front: usize) -> (f64, usize) {
+        let distance = |a: &i32, b: &i32| self.distance_between(a, b).unwrap_or(0.0);
+        let mut cheapest = (f64::MAX, front);
+        for position in front..=route.len() {
+            let increase = if position == 0 {
+                distance(point, &route[0])
+            } else if position == route.len() {
+                distance(&route[position - 1], point)
+            } else {
+                let (prev, next) = (&route[position - 1], &route[position]);
+                distance(prev, point) + distance(point, next) - distance(prev, next)
+            };
+            if is_shorter(increase, cheapest.0) {
+                cheapest = (increase, position);
+            }
+        }
+        cheapest
+    }
+}
+
+/// Astronomical unit in meters
+const AU: f64 = 149_597_870_700.0;
+/// A ship leaves the warp when it slows down to this speed in m/s
+const WARP_DROPOUT_SPEED: f64 = 100.0;
+
+#[derive(Debug, PartialEq, Clone)]
+struct WarpModel {
+    /// Warp speed in AU/s
+    warp_speed: f64,
+    /// Align time in seconds
+    align_time: f64,
+}
+impl Default for WarpModel {
+    fn default() -> Self {
+        Self {
+            warp_speed: 3.0,
+            align_time: 5.0,
+        }
+    }
+}
+impl WarpModel {
+    /// The time of a warp in seconds. The ship accelerates exponentially with
+    /// the rate of its warp speed and decelerates with the third of it (but not
+    /// faster than 2), cruising at the warp speed if the warp is long enough.
+    pub fn hop_time(&self, distance: f64) -> f64 {
+        if distance <= 0.0 {
+            return 0.0;
+        }
+        let accel = self.warp_speed;
+        let decel = (self.warp_speed / 3.0).min(2.0);
+        let max_speed = self.warp_speed * AU;
+        let (accel_distance, decel_distance) = (max_speed / accel, max_speed / decel);
+
+        let warp = if distance < accel_distance + decel_distance {
+            let peak = (distance * accel * decel / (accel + decel)).max(WARP_DROPOUT_SPEED);
+            peak.ln() / accel + (peak / WARP_DROPOUT_SPEED).ln() / decel
+        } else {
+            let cruise = (distance - accel_distance - decel_distance) / max_speed;
+            max_speed.ln() / accel + (max_speed / WARP_DROPOUT_SPEED).ln() / decel + cruise
+        };
+        self.align_time + warp
+    }
+
+    pub fn route_time(&self, hops: &[f64]) -> f64 {
+        hops.iter().map(|distance| self.hop_time(*distance)).sum()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct YieldModel {
+    /// Ore mined from a belt in m3
+    ore_per_belt: f64,
+    /// Mining rate in m3/s
+    mining_rate: f64,
+    /// Ore price in ISK/m3
+    ore_price: f64,
+}
+impl YieldModel {
+    /// Income of mining all the belts including the travel between them
+    pub fn isk_per_hour(&self, belts: usize, travel_time: f64) -> f64 {
+        let ore = self.ore_per_belt * belts as f64;
+        let time = travel_time + ore / self.mining_rate;
+        if time > 0.0 {
+            ore * self.ore_price / time * 3600.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+struct Hop {
+    id: i32,
+    name: String,
+    /// The distance from the previous place of the route
+    distance: f64,
+    #[serde(default)]
+    position: Position,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+struct RouteReport {
+    #[serde(default)]
+    schema_version: u32,
+    planet_id: i32,
+    distance: f64,
+    ordinal_distance: f64,
+    route: Vec<Hop>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    isk_per_hour: Option<f64>,
+    /// The warp from the last place to the station ending the route
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    station_distance: Option<f64>,
+    /// The cloud was larger than the slow threshold
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    slow: bool,
+    /// No route is shorter than this, given when the route isn't proved the shortest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lower_bound: Option<f64>,
+}
+impl RouteReport {
+    pub fn new(
+        cloud: &Cloud,
+        (distance, route): &(f64, Vec<i32>),
+        ordinal: &(f64, Vec<i32>),
+    ) -> Self {
+        let mut previous = None;
+        let hops = route
+            .iter()
+            .map(|id| {
+                let hop = Hop {
+                    id: *id,
+                    name: cloud.get_name(id).unwrap_or_default(),
+                    distance: previous
+                        .and_then(|prev| cloud.distance_between(prev, id))
+                        .unwrap_or(0.0),
+                    position: cloud
+                        .places
+                        .get(id)
+                        .map(|place| place.position.clone())
+                        .unwrap_or_default(),
+                };
+                previous = Some(id);
+                hop
+            })
+            .collect();
+
+        Self {
+            schema_version: REPORT_VERSION,
+            planet_id: cloud.planet_id,
+            distance: *distance,
+            ordinal_distance: ordinal.0,
+            route: hops,
+            isk_per_hour: None,
+            station_distance: None,
+            slow: false,
+            lower_bound: None,
+        }
+    }
+
+    /// Distances of the warps between the places of the route
+    pub fn hop_distances(&self) -> Vec<f64> {
+        self.route.iter().skip(1).map(|hop| hop.distance).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+struct SystemReport {
+    system_id: i32,
+    name: String,
+    security_status: f32,
+    clouds: Vec<RouteReport>,
+}
+
+/// A line of `--format ndjson`: the report of a cloud with the system it belongs to
+#[derive(Serialize, Debug, PartialEq)]
+struct CloudRecord<'a> {
+    system_id: i32,
+    system_name: &'a str,
+    #[serde(flatten)]
+    report: &'a RouteReport,
+}
+
+/// A location to warp to, the output of `--format bookmarks` is a JSON array of them:
+///
+/// ```json
+/// [{"label": "Tanoo 1.01", "name": "Tanoo I - Asteroid Belt 1", "item_id": 40000003,
+///   "system_id": 30000001, "x": 161366522880.0, "y": 21527715840.0, "z": -91092664320.0}]
+/// ```
+///
+/// The label is the system name, the number of the route and the step in it,
+/// so the bookmarks sort in the route order. The coordinates are in meters.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+struct Bookmark {
+    label: String,
+    name: String,
+    item_id: i32,
+    system_id: i32,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+impl Bookmark {
+    pub fn from_reports(reports: &[SystemReport]) -> Vec<Self> {
+        reports
+            .iter()
+            .flat_map(|system| {
+                system
+                    .clouds
+                    .iter()
+                    .enumerate()
+                    .flat_map(move |(n, cloud)| {
+                        cloud.route.iter().enumerate().map(move |(step, hop)| Self {
+                            label: format!("{} {}.{:02}", system.name, n + 1, step + 1),
+                            name: hop.name.clone(),
+                            item_id: hop.id,
+                            system_id: system.system_id,
+                            x: hop.position.x,
+                            y: hop.position.y,
+                            z: hop.position.z,
+                        })
+                    })
+            })
+            .collect()
+    }
+}
+
+/// The header and a row per system: its clouds, belts, the summed length
+/// of the shortest routes and the longest hop of them, the distances in meters
+fn summary_csv(reports: &[SystemReport]) -> String {
+    let quoted = |text: &str| {
+        if text.contains([',', '"', '\n']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_string()
+        }
+    };
+    let mut lines = vec!["system,system_id,security,clouds,belts,distance,longest_hop".to_string()];
+    for system in reports {
+        let belts = system
+            .clouds
+            .iter()
+            .map(|cloud| cloud.route.len())
+            .sum::<usize>();
+        let distance = system
+            .clouds
+            .iter()
+            .map(|cloud| cloud.distance)
+            .sum::<f64>();
+        let longest = system
+            .clouds
+            .iter()
+            .flat_map(|cloud| cloud.hop_distances())
+            .fold(0.0, f64::max);
+        lines.push(format!(
+            "{},{},{:.2},{},{belts},{distance:.0},{longest:.0}",
+            quoted(&system.name),
+            system.system_id,
+            system.security_status,
+            system.clouds.len()
+        ));
+    }
+    lines.join("\n")
+}
+
+/// The table of the systems from the best score down
+fn ranking_lines(
+    reports: &[SystemReport],
+    weights: &ScoreWeights,
+    style: &NumberStyle,
+    precision: usize,
+) -> Vec<String> {
+    let mut ranked = reports
+        .iter()
+        .map(|system| (weights.score(system), system))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    let width = reports
+        .iter()
+        .map(|system| system.name.chars().count())
+        .fold("System".len(), usize::max);
+    let mut lines = vec![format!(
+        "Rank {:width$} Security Belts {:>12} {:>8}",
+        "System", "Distance", "Score"
+    )];
+    for (rank, (score, system)) in ranked.iter().enumerate() {
+        let belts = system
+            .clouds
+            .iter()
+            .map(|cloud| cloud.route.len())
+            .sum::<usize>();
+        let distance = system
+            .clouds
+            .iter()
+            .map(|cloud| cloud.distance)
+            .sum::<f64>();
+        lines.push(format!(
+            "{:>4} {:width$} {:>8.2} {belts:>5} {:>12} {score:>8.2}",
+            rank + 1,
+            system.name,
+            system.security_status,
+            fmt(&distance, style, precision)
+        ));
+    }
+    lines
+}
+
+/// The Graphviz graph of the routes with a cluster per cloud, render it by `neato -n`
+/// to keep the belts at their positions or by `dot` to lay them out
+fn dot_graph(reports: &[SystemReport], style: &NumberStyle, precision: usize) -> String {
+    let quoted = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut lines = vec!["digraph route {".to_string()];
+    for system in reports {
+        for (n, cloud) in system.clouds.iter().enumerate() {
+            lines.push(format!("\tsubgraph cluster_{}_{n} {{", system.system_id));
+            lines.push(format!(
+                "\t\tlabel={};",
+                quoted(&format!("{} {}", system.name, cloud.planet_id))
+            ));
+            for hop in &cloud.route {
+                lines.push(format!(
+                    "\t\t{} [label={}, pos=\"{:.0},{:.0}!\"];",
+                    hop.id,
+                    quoted(&hop.name),
+                    hop.position.x / 1e6,
+                    hop.position.z / 1e6
+                ));
+            }
+            for (from, to) in cloud.route.iter().tuple_windows() {
+                lines.push(format!(
+                    "\t\t{} -> {} [label={}, color=red, penwidth=2];",
+                    from.id,
+                    to.id,
+                    quoted(&fmt(&to.distance, style, precision))
+                ));
+            }
+            lines.push("\t}".to_string());
+        }
+    }
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+/// The ESI data kept between the runs. Every item is written once loaded
+/// so an interrupted run leaves what it got for the next one, a broken item
+/// is as good as a missing one.
+trait Cache: std::fmt::Debug + Send + Sync {
+    fn belt(&self, id: &i32) -> Option<AsteroidBelt>;
+    fn put_belt(&self, id: &i32, belt: &AsteroidBelt) -> anyhow::Result<()>;
+    fn system(&self, id: &i32) -> Option<System>;
+    fn put_system(&self, id: &i32, system: &System) -> anyhow::Result<()>;
+    /// The id of the system of the name, the case is ignored
+    fn system_id(&self, name: &str) -> Option<i32>;
+    fn put_system_id(&self, name: &str, id: &i32) -> anyhow::Result<()>;
+    fn route(&self, key: &str) -> Option<CachedRoute>;
+    fn put_route(&self, key: &str, route: &CachedRoute) -> anyhow::Result<()>;
+
+    fn has_belt(&self, id: &i32) -> bool {
+        self.belt(id).is_some()
+    }
+}
+
+/// A route of the cache with the moment it was solved at
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct CachedRoute {
+    /// Seconds since the Unix epoch
+    solved_at: u64,
+    route: Vec<i32>,
+}
+impl CachedRoute {
+    pub fn new(route: &[i32]) -> Self {
+        Self {
+            solved_at: unix_time(),
+            route: route.to_vec(),
+        }
+    }
+
+    /// Whether the route was solved no more than `ttl` seconds before `now`
+    pub fn is_fresh(&self, ttl: u64, now: u64) -> bool {
+        now.saturating_sub(self.solved_at) <= ttl
+    }
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The key of the route of the cloud in the cache: the hash of the version of the tool,
+/// the places and the options but the deadline
+fn route_key(cloud: &Cloud, options: &RouteOptions) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    cloud.planet_id.hash(&mut hasher);
+    for place in cloud.places_sorted() {
+        (place.id, &place.name).hash(&mut hasher);
+        let position = &place.position;
+        [position.x, position.y, position.z]
+            .map(f64::to_bits)
+            .hash(&mut hasher);
+    }
+    let options = RouteOptions {
+        deadline: None,
+        ..options.clone()
+    };
+    format!("{options:?}").hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// The cached route of the cloud unless it is older than `ttl` seconds or doesn't fit it
+fn fresh_route(cache: &dyn Cache, cloud: &Cloud, key: &str, ttl: u64) -> Option<Vec<i32>> {
+    let cached = cache.route(key)?;
+    if !cached.is_fresh(ttl, unix_time()) {
+        info!("The cached route {key} has expired");
+        return None;
+    }
+    cloud
+        .validate_route(&cached.route)
+        .map_err(|err| warn!("Ignoring the cached route {key}: {err}"))
+        .ok()?;
+    Some(cached.route)
+}
+
+/// The cache of the `--cache` options if any
+fn open_cache(args: &Args) -> anyhow::Result<Option<Box<dyn Cache>>> {
+    let Some(ref path) = args.cache else {
+        return Ok(None);
+    };
+    match args.cache_backend {
+        CacheBackend::Json => Ok(Some(Box::new(JsonCache::new(path)?))),
+        #[cfg(feature = "sqlite")]
+        CacheBackend::Sqlite => Ok(Some(Box::new(SqliteCache::open(path)?))),
+        #[cfg(not(feature = "sqlite"))]
+        CacheBackend::Sqlite => {
+            anyhow::bail!("The SQLite cache needs the build with `--features sqlite`")
+        }
+    }
+}
+
+/// The belts kept as `<belt_id>.json` files, the systems as `system_<system_id>.json`,
+/// the routes as `route_<key>.json` and the system ids of the names in `names.json`
+#[derive(Debug, Clone)]
+struct JsonCache {
+    dir: std::path::PathBuf,
+}
+impl JsonCache {
+    pub fn new(dir: &std::path::Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(&self, file: &str) -> Option<T> {
+        let text = std::fs::read_to_string(self.dir.join(file)).ok()?;
+        serde_json::from_str(&text)
+            .map_err(|err| warn!("Ignoring the cached {file}: {err}"))
+            .ok()
+    }
+
+    /// Writes the value next to its file and renames it, a reader never sees a partial file
+    fn write<T: Serialize>(&self, file: &str, value: &T) -> anyhow::Result<()> {
+        let path = self.dir.join(file);
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_string(value)?)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+impl Cache for JsonCache {
+    fn belt(&self, id: &i32) -> Option<AsteroidBelt> {
+        self.read(&format!("{id}.json"))
+    }
+
+    fn put_belt(&self, id: &i32, belt: &AsteroidBelt) -> anyhow::Result<()> {
+        self.write(&format!("{id}.json"), belt)
+    }
+
+    fn system(&self, id: &i32) -> Option<System> {
+        self.read(&format!("system_{id}.json"))
+    }
+
+    fn put_system(&self, id: &i32, system: &System) -> anyhow::Result<()> {
+        self.write(&format!("system_{id}.json"), system)
+    }
+
+    fn system_id(&self, name: &str) -> Option<i32> {
+        let names: HashMap<String, i32> = self.read("names.json")?;
+        names.get(&name.to_lowercase()).cloned()
+    }
+
+    fn put_system_id(&self, name: &str, id: &i32) -> anyhow::Result<()> {
+        let mut names: HashMap<String, i32> = self.read("names.json").unwrap_or_default();
+        names.insert(name.to_lowercase(), *id);
+        self.write("names.json", &names)
+    }
+
+    fn route(&self, key: &str) -> Option<CachedRoute> {
+        self.read(&format!("route_{key}.json"))
+    }
+
+    fn put_route(&self, key: &str, route: &CachedRoute) -> anyhow::Result<()> {
+        self.write(&format!("route_{key}.json"), route)
+    }
+}
+
+/// The belts, the systems, the names and the routes in the tables of a SQLite database
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+struct SqliteCache {
+    connection: std::sync::Mutex<rusqlite::Connection>,
+}
+#[cfg(feature = "sqlite")]
+impl SqliteCache {
+    pub fn open(path: &std::path::Path) -> anyhow::Result<Self> {
+        let connection = rusqlite::Connection::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS belts (
+                 id INTEGER PRIMARY KEY,
+                 system_id INTEGER NOT NULL,
+                 name TEXT NOT NULL,
+                 x REAL NOT NULL,
+                 y REAL NOT NULL,
+                 z REAL NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS belts_of_system ON belts (system_id);
+             CREATE TABLE IF NOT EXISTS systems (
+                 id INTEGER PRIMARY KEY,
+                 json TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS names (
+                 name TEXT PRIMARY KEY COLLATE NOCASE,
+                 system_id INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS routes (
+                 key TEXT PRIMARY KEY,
+                 solved_at INTEGER NOT NULL,
+                 route TEXT NOT NULL
+             );",
+        )?;
+        Ok(Self {
+            connection: std::sync::Mutex::new(connection),
+        })
+    }
+
+    fn query<T>(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+        row: impl FnOnce(&rusqlite::Row) -> rusqlite::Result<T>,
+    ) -> Option<T> {
+        use rusqlite::OptionalExtension;
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        connection
+            .query_row(sql, params, row)
+            .optional()
+            .map_err(|err| warn!("Ignoring the cache: {err}"))
+            .ok()
+            .flatten()
+    }
+
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) -> anyhow::Result<()> {
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        connection.execute(sql, params)?;
+        Ok(())
+    }
+}
+#[cfg(feature = "sqlite")]
+impl Cache for SqliteCache {
+    fn belt(&self, id: &i32) -> Option<AsteroidBelt> {
+        self.query(
+            "SELECT name, x, y, z, system_id FROM belts WHERE id = ?1",
+            [id],
+            |row| {
+                Ok(AsteroidBelt {
+                    name: row.get(0)?,
+                    position: Position::new(&row.get(1)?, &row.get(2)?, &row.get(3)?),
+                    system_id: row.get(4)?,
+                })
+            },
+        )
+    }
+
+    fn put_belt(&self, id: &i32, belt: &AsteroidBelt) -> anyhow::Result<()> {
+        let position = &belt.position;
+        self.execute(
+            "INSERT OR REPLACE INTO belts (id, system_id, name, x, y, z)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                id,
+                belt.system_id,
+                belt.name,
+                position.x,
+                position.y,
+                position.z
+            ],
+        )
+    }
+
+    fn system(&self, id: &i32) -> Option<System> {
+        let json = self.query("SELECT json FROM systems WHERE id = ?1", [id], |row| {
+            row.get::<_, String>(0)
+        })?;
+        serde_json::from_str(&json)
+            .map_err(|err| warn!("Ignoring the cached system {id}: {err}"))
+            .ok()
+    }
+
+    fn put_system(&self, id: &i32, system: &System) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO systems (id, json) VALUES (?1, ?2)",
+            rusqlite::params![id, serde_json::to_string(system)?],
+        )
+    }
+
+    fn system_id(&self, name: &str) -> Option<i32> {
+        self.query(
+            "SELECT system_id FROM names WHERE name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+    }
+
+    fn put_system_id(&self, name: &str, id: &i32) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO names (name, system_id) VALUES (?1, ?2)",
+            rusqlite::params![name, id],
+        )
+    }
+
+    fn route(&self, key: &str) -> Option<CachedRoute> {
+        let (solved_at, json) = self.query(
+            "SELECT solved_at, route FROM routes WHERE key = ?1",
+            [key],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+        )?;
+        let route = serde_json::from_str(&json)
+            .map_err(|err| warn!("Ignoring the cached route {key}: {err}"))
+            .ok()?;
+        Some(CachedRoute {
+            solved_at: solved_at.try_into().unwrap_or_default(),
+            route,
+        })
+    }
+
+    fn put_route(&self, key: &str, route: &CachedRoute) -> anyhow::Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO routes (key, solved_at, route) VALUES (?1, ?2, ?3)",
+            rusqlite::params![
+                key,
+                i64::try_from(route.solved_at)?,
+                serde_json::to_string(&route.route)?
+            ],
+        )
+    }
+}
+
+/// The systems and their asteroid belts loaded ahead of the routing
+#[derive(Debug, Default)]
+struct Prefetch {
+    systems: HashMap<i32, System>,
+    belts: HashMap<i32, AsteroidBelt>,
+}
+impl Prefetch {
+    /// Loads all the systems concurrently, then all the belts of all of them
+    /// but the cached ones
+    pub async fn load(esi: &Esi, ids: &[i32], cache: Option<&dyn Cache>) -> anyhow::Result<Self> {
+        let cached = ids
+            .iter()
+            .filter_map(|id| {
+                cache
+                    .and_then(|cache| cache.system(id))
+                    .map(|system| (*id, system))
+            })
+            .collect::<HashMap<i32, System>>();
+        let missing = ids
+            .iter()
+            .filter(|id| !cached.contains_key(id))
+            .cloned()
+            .collect::<Vec<i32>>();
+        let mut systems = load_all(&missing, &esi.concurrency, |id| {
+            let esi = esi.clone();
+            async move {
+                System::load(&esi, &id)
+                    .await
+                    .with_context(|| format!("Failed to load the system {id}"))
+            }
+        })
+        .await?;
+        if let Some(cache) = cache {
+            for (id, system) in &systems {
+                cache.put_system(id, system)?;
+            }
+        }
+        systems.extend(cached);
+
+        let belt_ids = systems
+            .values()
+            .flat_map(|system| system.planets.iter().flatten())
+            .flat_map(|planet| planet.asteroid_belts.iter().flatten())
+            .filter(|id| cache.is_none_or(|cache| !cache.has_belt(id)))
+            .cloned()
+            .collect::<Vec<i32>>();
+        info!(
+            "Prefetching {} belts of {} systems",
+            belt_ids.len(),
+            systems.len()
+        );
+        let belts = load_all(&belt_ids, &esi.concurrency, |id| {
+            let esi = esi.clone();
+            async move {
+                AsteroidBelt::load(&esi, &id)
+                    .await
+                    .with_context(|| format!("Failed to load the asteroid belt {id}"))
+            }
+        })
+        .await?;
+        if let Some(cache) = cache {
+            for (id, belt) in &belts {
+                cache.put_belt(id, belt)?;
+            }
+        }
+
+        Ok(Self { systems, belts })
+    }
+}
+
+/// Runs the loads of the ids with at most the current limit of them at once,
+/// the limit is read again every time a load completes
+async fn load_all<T, F, J>(
+    ids: &[i32],
+    concurrency: &Concurrency,
+    load: F,
+) -> anyhow::Result<HashMap<i32, T>>
+where
+    T: Send + 'static,
+    F: Fn(i32) -> J,
+    J: std::future::Future<Output = anyhow::Result<T>> + Send + 'static,
+{
+    let mut pending = ids.iter().cloned().unique().collect::<Vec<i32>>();
+    pending.reverse();
+    let mut tasks = tokio::task::JoinSet::new();
+    let mut loaded = HashMap::new();
+    loop {
+        while tasks.len() < concurrency.limit() {
+            let Some(id) = pending.pop() else { break };
+            let job = load(id);
+            tasks.spawn(async move { (id, job.await) });
+        }
+        let Some(task) = tasks.join_next().await else {
+            break;
+        };
+        let (id, result) = task?;
+        loaded.insert(id, result?);
+    }
+    Ok(loaded)
+}
+
+async fn load_system_asteroids(
+    esi: &Esi,
+    system: &System,
+    template: &NameTemplate,
+    prefetch: &Prefetch,
+    cache: Option<&dyn Cache>,
+    positions: &HashMap<i32, Position>,
+    strict: bool,
+) -> anyhow::Result<Vec<Cloud>> {
+    let mut clouds = Vec::new();
+    if let Some(ref planets) = system.planets {
+        for planet in planets {
+            let mut cloud = Cloud::with_planet(&planet.planet_id);
+            let mut belts = Vec::new();
+            if let Some(ref ids) = planet.asteroid_belts {
+                for id in ids {
+                    let cached = || cache.and_then(|cache| cache.belt(id));
+                    let mut belt = match prefetch.belts.get(id).cloned().or_else(cached) {
+                        Some(belt) => belt,
+                        None => {
+                            let belt = AsteroidBelt::load(esi, id).await.with_context(|| {
+                                format!("Failed to load the asteroid belt {id}")
+                            })?;
+                            if let Some(cache) = cache {
+                                cache.put_belt(id, &belt)?;
+                            }
+                            belt
+                        }
+                    };
+                    if let Some(position) = positions.get(id) {
+                        belt.position = position.clone();
+                    }
+                    eprintln!("Belt: {id} - {}: {}", belt.name, belt.position);
+                    belts.push(if strict {
+                        Place::checked(id, &belt.name, &belt.position, template)?
+                    } else {
+                        Place::parsed(id, &belt.name, &belt.position, template)
+                    });
+                }
+            }
+            if strict {
+                cloud.check_places(&belts).with_context(|| {
+                    format!("Anomalies in the belts of the planet {}", planet.planet_id)
+                })?;
+            }
+            cloud.add_places(belts);
+            if !cloud.is_empty() {
+                clouds.push(cloud);
+            }
+        }
+    }
+    Ok(clouds)
+}
+
+/// Adds every point to the cloud with the place closest to it, the points get
+/// the negative ids -1, -2, ... in their order
+fn add_waypoints(clouds: &mut [Cloud], points: &[Waypoint]) {
+    for (n, point) in points.iter().enumerate() {
+        let closest = clouds.iter_mut().min_by(|a, b| {
+            let distance = |cloud: &Cloud| {
+                cloud
+                    .places
+                    .values()
+                    .map(|place| Position::distance(&place.position, &point.position))
+                    .fold(f64::INFINITY, f64::min)
+            };
+            distance(a).total_cmp(&distance(b))
+        });
+        match closest {
+            Some(cloud) => {
+                let id = -(n as i32 + 1);
+                cloud.add_place(Place::named(&id, &point.name, &point.position));
+            }
+            None => warn!("No cloud to add `{}` to", point.name),
+        }
+    }
+}
+
+/// The belt positions of the file: a JSON object of the ids and the positions
+/// for `.json`, the `id,x,y,z` lines otherwise, a header line is skipped
+fn read_belt_positions(path: &std::path::Path) -> anyhow::Result<HashMap<i32, Position>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+    {
+        return serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", path.display()));
+    }
+    let mut positions = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() || (n == 0 && line.starts_with("id")) {
+            continue;
+        }
+        match parse_belt_position(line) {
+            Some((id, position)) => positions.insert(id, position),
+            None => anyhow::bail!(
+                "`{line}` at {}:{} is not the id,x,y,z of a belt",
+                path.display(),
+                n + 1
+            ),
+        };
+    }
+    Ok(positions)
+}
+
+/// The id and the position of the `id,x,y,z` line
+fn parse_belt_position(line: &str) -> Option<(i32, Position)> {
+    let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+    match fields.as_slice() {
+        [id, x, y, z] => Some((
+            id.parse().ok()?,
+            Position::new(&x.parse().ok()?, &y.parse().ok()?, &z.parse().ok()?),
+        )),
+        _ => None,
+    }
+}
+
+/// The belt files of the options, read once before any system is loaded
+#[derive(Debug, Default)]
+struct BeltFiles {
+    /// The belts of `--belt-whitelist-file`
+    whitelist: Option<Vec<i32>>,
+    /// The positions replacing the ESI ones, empty unless `--belt-position-source file`
+    positions: HashMap<i32, Position>,
+    /// The route of `--import-route`
+    imported: Option<ImportedRoute>,
+}
+impl BeltFiles {
+    pub fn read(args: &Args) -> anyhow::Result<Self> {
+        let whitelist = match args.belt_whitelist_file {
+            Some(ref path) => Some(read_belt_ids(path)?),
+            None => None,
+        };
+        let positions = match (args.belt_position_source, &args.belt_positions) {
+            (PositionSource::File, Some(path)) => read_belt_positions(path)?,
+            _ => HashMap::new(),
+        };
+        let imported = match args.import_route {
+            Some(ref path) => Some(read_imported_route(path)?),
+            None => None,
+        };
+        Ok(Self {
+            whitelist,
+            positions,
+            imported,
+        })
+    }
+}
+
+/// The route of the file, the belt ids or a TSPLIB tour
+fn read_imported_route(path: &std::path::Path) -> anyhow::Result<ImportedRoute> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// The belt ids of the file, one per line, the blank lines and the `#` comments are skipped
+fn read_belt_ids(path: &std::path::Path) -> anyhow::Result<Vec<i32>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut ids = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if !line.is_empty() {
+            ids.push(line.parse::<i32>().with_context(|| {
+                format!("`{line}` at {}:{} is not a belt id", path.display(), n + 1)
+            })?);
+        }
+    }
+    Ok(ids)
+}
+
+async fn load_system_planets(esi: &Esi, system: &System) -> anyhow::Result<Vec<Cloud>> {
+    let mut cloud = Cloud::new();
+    if let Some(ref planets) = system.planets {
+        for planet in planets {
+            let info = PlanetInfo::load(esi, &planet.planet_id)
+                .await
+                .with_context(|| format!("Failed to load the planet {}", planet.planet_id))?;
+            eprintln!(
+                "Planet: {} - {}: {}",
+                info.planet_id, info.name, info.position
+            );
+            cloud.add_place(Place::named(&info.planet_id, &info.name, &info.position));
+        }
+    }
+    if cloud.is_empty() {
+        Ok(vec![])
+    } else {
+        Ok(vec![cloud])
+    }
+}
+
+/// Sorts the clouds by the order known before they are routed,
+/// the distance order is [distance_order] of the solved routes
+fn sort_clouds(clouds: &mut [Cloud], order: &CloudOrder) {
+    match order {
+        CloudOrder::Planet | CloudOrder::Distance => clouds.sort_by_key(|cloud| cloud.planet_id),
+        CloudOrder::Belts => clouds.sort_by_key(|cloud| cloud.len()),
+        CloudOrder::Name => clouds.sort_by_cached_key(|cloud| cloud.name()),
+    }
+}
+
+/// The indices of the solved routes by the length of the shortest ones, the shortest first
+fn distance_order(solved: &[(Route, Route)]) -> Vec<usize> {
+    (0..solved.len())
+        .sorted_by(|a, b| solved[*a].1 .0.total_cmp(&solved[*b].1 .0))
+        .collect()
+}
+
+/// The items in the order of their indices
+fn reorder<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut items = items.into_iter().map(Some).collect::<Vec<_>>();
+    order.iter().filter_map(|n| items[*n].take()).collect()
+}
+
+/// How much shorter the route is than the ordinal one, in percent
+fn saving_percent(ordinal: f64, shortest: f64) -> f64 {
+    if ordinal > 0.0 {
+        (ordinal - shortest) / ordinal * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// How much longer the route is than the lower bound
+fn gap_percent(distance: f64, bound: f64) -> f64 {
+    if bound > 0.0 {
+        (distance - bound) / bound * 100.0
+    } else {
+        0.0
+    }
+}
+
+fn group_thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+fn fmt(distance: &f64, style: &NumberStyle, precision: usize) -> String {
+    let scale = 10f64.powi(precision as i32);
+    let megameters = (distance / 1000000.0 * scale).round() / scale;
+    let plain = format!("{megameters:.precision$}");
+    // The distances rounding to nothing would look like the same position
+    if megameters == 0.0 && *distance != 0.0 {
+        return if (distance / 1000.0).abs() >= 0.5 {
+            format!("{:.0} km", distance / 1000.0)
+        } else if distance.abs() >= 0.5 {
+            format!("{distance:.0} m")
+        } else {
+            "<1 m".to_string()
+        };
+    }
+    match style {
+        NumberStyle::Plain => format!("{plain} Mm"),
+        NumberStyle::Grouped => {
+            let (whole, fraction) = plain
+                .split_once('.')
+                .map_or((plain.as_str(), String::new()), |(whole, fraction)| {
+                    (whole, format!(".{fraction}"))
+                });
+            let whole = group_thousands(whole.parse::<u64>().unwrap_or_default());
+            format!("{whole}{fraction} Mm")
+        }
+        NumberStyle::Scaled if megameters >= 1000.0 => {
+            format!("{:.*} Gm", precision.max(2), distance / 1000000000.0)
+        }
+        NumberStyle::Scaled => format!("{plain} Mm"),
+    }
+}
+
+/// The belts added to and removed from the routes of the clouds, the changes of their order
+/// and of the lengths. The clouds are matched by the system and the planet.
+fn diff_reports(
+    old: &[SystemReport],
+    new: &[SystemReport],
+    style: &NumberStyle,
+    precision: usize,
+) -> Vec<String> {
+    type Clouds<'a> = Vec<((i32, i32), (&'a SystemReport, &'a RouteReport))>;
+    fn clouds(reports: &[SystemReport]) -> Clouds<'_> {
+        reports
+            .iter()
+            .flat_map(|system| {
+                system
+                    .clouds
+                    .iter()
+                    .map(move |cloud| ((system.system_id, cloud.planet_id), (system, cloud)))
+            })
+            .collect()
+    }
+    let (old, new) = (clouds(old), clouds(new));
+    let find = |clouds: &Clouds, key| clouds.iter().any(|(k, _)| *k == key);
+    let mut lines = Vec::new();
+    for (key, (system, cloud)) in &old {
+        if !find(&new, *key) {
+            lines.push(format!(
+                "{} planet {}: the cloud is removed",
+                system.name, cloud.planet_id
+            ));
+        }
+    }
+    for (key, (system, cloud)) in &new {
+        let title = format!("{} planet {}", system.name, cloud.planet_id);
+        let Some((_, (_, before))) = old.iter().find(|(k, _)| k == key) else {
+            lines.push(format!("{title}: the cloud is added"));
+            continue;
+        };
+        let ids =
+            |report: &RouteReport| report.route.iter().map(|hop| hop.id).collect::<Vec<i32>>();
+        let (was, now) = (ids(before), ids(cloud));
+        for hop in cloud.route.iter().filter(|hop| !was.contains(&hop.id)) {
+            lines.push(format!("{title}: + `{}`", hop.name));
+        }
+        for hop in before.route.iter().filter(|hop| !now.contains(&hop.id)) {
+            lines.push(format!("{title}: - `{}`", hop.name));
+        }
+        let common = |route: &[i32], other: &[i32]| {
+            route
+                .iter()
+                .filter(|id| other.contains(id))
+                .cloned()
+                .collect::<Vec<i32>>()
+        };
+        if common(&was, &now) != common(&now, &was) {
+            lines.push(format!("{title}: the order is changed"));
+        }
+        if !approx_eq(before.distance, cloud.distance) {
+            let change = cloud.distance - before.distance;
+            let sign = if change < 0.0 { '-' } else { '+' };
+            lines.push(format!(
+                "{title}: {} -> {} ({sign}{})",
+                fmt(&before.distance, style, precision),
+                fmt(&cloud.distance, style, precision),
+                fmt(&change.abs(), style, precision)
+            ));
+        }
+    }
+    lines
+}
+
+/// The belts of the cloud as `id - name` in the order
+fn belt_lines(cloud: &Cloud, order: &BeltList, route: &[i32]) -> Vec<String> {
+    let ids = match order {
+        BeltList::Name => cloud.get_ids_sorted_by_name(),
+        BeltList::Id => cloud.ids(),
+        BeltList::Route => route.to_vec(),
+    };
+    std::iter::once("Belts:".to_string())
+        .chain(
+            ids.iter()
+                .map(|id| format!("\t{id} - {}", cloud.get_name(id).unwrap_or_default())),
+        )
+        .collect()
+}
+
+/// A route computed outside
+#[derive(Debug, PartialEq, Clone)]
+enum ImportedRoute {
+    /// The belt ids in the route order
+    Ids(Vec<i32>),
+    /// The TSPLIB nodes from 1 in the route order, of the planet of the instance name
+    Tour {
+        planet_id: Option<i32>,
+        nodes: Vec<usize>,
+    },
+}
+impl std::str::FromStr for ImportedRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let Some((header, section)) = text.split_once("TOUR_SECTION") else {
+            let ids = text
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|token| !token.is_empty())
+                .map(|token| {
+                    token
+                        .parse::<i32>()
+                        .with_context(|| format!("`{token}` is not a belt id"))
+                })
+                .collect::<anyhow::Result<Vec<i32>>>()?;
+            return Ok(Self::Ids(ids));
+        };
+        let planet_id = header
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim() == "NAME")
+            .and_then(|(_, name)| name.trim().strip_prefix("planet_"))
+            .and_then(|name| name.split('.').next()?.parse::<i32>().ok());
+        let mut nodes = Vec::new();
+        for token in section.split_whitespace() {
+            if token == "-1" || token == "EOF" {
+                break;
+            }
+            nodes.push(
+                token
+                    .parse::<usize>()
+                    .with_context(|| format!("`{token}` is not a tour node"))?,
+            );
+        }
+        Ok(Self::Tour { planet_id, nodes })
+    }
+}
+
+/// The pairwise distances of a cloud, all zeros for less than two places
+#[derive(Debug, PartialEq, Clone, Default)]
+struct DistanceStats {
+    min: f64,
+    max: f64,
+    mean: f64,
+    median: f64,
+    /// The distance between the farthest pair
+    diameter: f64,
+}
+
+/// The length of a route and the places along it
+type Route = (f64, Vec<i32>);
+
+/// The text output of a system: the ordinal and the shortest routes of every cloud,
+/// or only the shortest route when the system has a single cloud
+fn system_lines(
+    clouds: &[Cloud],
+    routes: &[(Route, Route)],
+    reports: &[RouteReport],
+    args: &Args,
+) -> Vec<String> {
+    let palette = Palette::new(args.color);
+    let single = 1 == clouds.len();
+    let mut lines = Vec::new();
+    if !single {
+        lines.push(format!("\n\t{}", palette.header("-=[Ordinal route]=-")));
+        for (cloud, (ordinal, _)) in clouds.iter().zip(routes) {
+            lines.extend(route_lines(cloud, ordinal.clone(), args));
+        }
+        lines.push(format!("\n\t{}", palette.header("-=[Shortest route]=-")));
+    }
+
+    for ((cloud, (_, shortest)), report) in clouds.iter().zip(routes).zip(reports) {
+        if let Some(order) = args.list_belts {
+            lines.extend(belt_lines(cloud, &order, &shortest.1));
+        }
+        if args.stats {
+            let stats = cloud.distance_stats();
+            let style = &args.number_style;
+            lines.push(format!(
+                "Distances: min {}, max {}, mean {}, median {}, diameter {}",
+                fmt(&stats.min, style, args.precision),
+                fmt(&stats.max, style, args.precision),
+                fmt(&stats.mean, style, args.precision),
+                fmt(&stats.median, style, args.precision),
+                fmt(&stats.diameter, style, args.precision)
+            ));
+        }
+        lines.extend(route_lines(cloud, shortest.clone(), args));
+        if report.ordinal_distance > 0.0 {
+            lines.push(format!(
+                "Saved {:.0}% vs ordinal ({} → {})",
+                saving_percent(report.ordinal_distance, report.distance),
+                fmt(&report.ordinal_distance, &args.number_style, args.precision),
+                fmt(&report.distance, &args.number_style, args.precision)
+            ));
+        }
+        if let Some(bound) = report.lower_bound {
+            lines.push(format!(
+                "Lower bound {}, within {:.0}% of optimal",
+                fmt(&bound, &args.number_style, args.precision),
+                gap_percent(report.distance, bound)
+            ));
+        }
+        if args.route_metric_summary {
+            if let Some(ratio) = cloud.detour_ratio(&shortest.1) {
+                lines.push(format!("Detour ratio: {ratio:.2}"));
+            }
+        }
+        if let Some(isk_per_hour) = report.isk_per_hour {
+            lines.push(format!(
+                "Estimated income: {} ISK/hour",
+                group_thousands(isk_per_hour.round() as u64)
+            ));
+        }
+    }
+    lines
+}
+
+/// The warp instructions along the route with the distance covered so far
+fn route_lines(cloud: &Cloud, (minimum, route): (f64, Vec<i32>), args: &Args) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut step = 1;
+    let palette = Palette::new(args.color);
+    let longest = route
+        .iter()
+        .tuple_windows()
+        .map(|(a, b)| cloud.distance_between(a, b).unwrap_or(0.0))
+        .fold(0.0, f64::max);
+    let id_of = |id: &i32| {
+        if args.show_ids {
+            format!(" (id {id})")
+        } else {
+            String::new()
+        }
+    };
+    if 1 == route.len() {
+        let id = route[0];
+        let name = cloud.get_name(&id).unwrap_or_default();
+        lines.push(format!(
+            "{:>2} Warp to `{}`{}",
+            step,
+            palette.name(&name),
+            id_of(&id)
+        ));
+        if let Some(Endpoint::At(ref station)) = args.end_at_station {
+            let leg = cloud.station_leg(&route, station);
+            lines.push(format!(
+                "Return to station - {}",
+                fmt(&leg, &args.number_style, args.precision)
+            ));
+        }
+    } else {
+        let mut first_time = true;
+        let mut covered = 0.0;
+        route.iter().reduce(|a, b| {
+            let dist = cloud.distance_between(a, b).unwrap_or(0.0);
+            covered += dist;
+            let name_a = cloud.get_name(a).unwrap_or_default();
+            let name_b = cloud.get_name(b).unwrap_or_default();
+            if first_time {
+                lines.push(format!(
+                    "{:>2} Warp to `{}`{}",
+                    step,
+                    palette.name(&name_a),
+                    id_of(a)
+                ));
+                first_time = false;
+                step += 1;
+            }
+
+            let bearing = match (cloud.places.get(a), cloud.places.get(b)) {
+                (Some(from), Some(to)) if args.bearings => format!(
+                    ", bearing {:03.0}°",
+                    Position::bearing(&from.position, &to.position)
+                ),
+                _ => String::new(),
+            };
+            let hop = fmt(&dist, &args.number_style, args.precision);
+            let hop = if route.len() > 2 && dist == longest {
+                palette.warning(&hop)
+            } else {
+                hop
+            };
+            lines.push(format!(
+                "{:>2} Warp to `{}`{} - {hop} (cum {}){bearing}",
+                step,
+                palette.name(&name_b),
+                id_of(b),
+                fmt(&covered, &args.number_style, args.precision)
+            ));
+            step += 1;
+            b
+        });
+        let leg = match args.end_at_station {
+            Some(Endpoint::At(ref station)) => {
+                let leg = cloud.station_leg(&route, station);
+                lines.push(format!(
+                    "Return to station - {}",
+                    fmt(&leg, &args.number_style, args.precision)
+                ));
+                leg
+            }
+            _ => 0.0,
+        };
+        lines.push(format!(
+            "The length of the route: {}",
+            palette.total(&fmt(&(minimum + leg), &args.number_style, args.precision))
+        ));
+        if args.round_trip {
+            let (open, back) = cloud.route_with_return_total(&route);
+            lines.push(format!(
+                "Route {}, return +{}, round trip {}",
+                fmt(&open, &args.number_style, args.precision),
+                fmt(&back, &args.number_style, args.precision),
+                fmt(&(open + back), &args.number_style, args.precision)
+            ));
+        }
+    }
+    lines
+}
+
+/// Writes the clouds of the system as `<system_id>_<n>.json` files
+fn dump_clouds(
+    dir: &std::path::Path,
+    system_id: &i32,
+    clouds: &[Cloud],
+) -> anyhow::Result<Vec<std::path::PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut paths = Vec::new();
+    for (n, cloud) in clouds.iter().enumerate() {
+        let path = dir.join(format!("{system_id}_{n}.json"));
+        std::fs::write(&path, serde_json::to_string_pretty(cloud)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn thread_pool(threads: usize) -> anyhow::Result<rayon::ThreadPool> {
+    Ok(rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?)
+}
+
+/// The ordinal and the shortest routes of the clouds in their order passed to `finish`
+/// with the index of the cloud as soon as the cloud is solved, the results of `finish`
+/// in the order of the clouds. The clouds are solved in parallel on the pool unless
+/// their route is fixed already.
+fn solve_clouds<R: Send>(
+    pool: &rayon::ThreadPool,
+    clouds: &[Cloud],
+    options: &[RouteOptions],
+    fixed: &[Option<Vec<i32>>],
+    finish: impl Fn(usize, &Cloud, &RouteOptions, (Route, Route)) -> R + Sync,
+) -> Vec<R> {
+    pool.install(|| {
+        clouds
+            .par_iter()
+            .zip(options)
+            .zip(fixed)
+            .enumerate()
+            .map(|(n, ((cloud, options), fixed))| {
+                let shortest = match fixed {
+                    Some(route) => (cloud.route_distance(route), route.clone()),
+                    None => cloud.solve(options),
+                };
+                finish(n, cloud, options, (cloud.get_ordinal_route(), shortest))
+            })
+            .collect()
+    })
+}
+
+/// Writes the lines coming in any order by their indices, every line as soon as
+/// all the lines before it are written
+#[derive(Debug, Default)]
+struct OrderedLines {
+    /// The index of the next line to write and the lines waiting for it
+    pending: std::sync::Mutex<(usize, std::collections::BTreeMap<usize, String>)>,
+}
+impl OrderedLines {
+    pub fn push(&self, index: usize, line: String, mut write: impl FnMut(&str)) {
+        let mut pending = self.pending.lock().unwrap_or_else(|err| err.into_inner());
+        let (next, waiting) = &mut *pending;
+        waiting.insert(index, line);
+        while let Some(line) = waiting.remove(next) {
+            write(&line);
+            *next += 1;
+        }
+    }
+}
+
+async fn make_route(
+    esi: &Esi,
+    pool: &rayon::ThreadPool,
+    cache: Option<&dyn Cache>,
+    files: &BeltFiles,
+    id: &i32,
+    args: &Args,
+    prefetch: &Prefetch,
+) -> anyhow::Result<SystemReport> {
+    let cached = || cache.and_then(|cache| cache.system(id));
+    let system = match prefetch.systems.get(id).cloned().or_else(cached) {
+        Some(system) => system,
+        None => {
+            let system = System::load(esi, id)
+                .await
+                .with_context(|| format!("Failed to load the system {id}"))?;
+            if let Some(cache) = cache {
+                cache.put_system(id, &system)?;
+            }
+            system
+        }
+    };
+    info!("system_name: {}", system.name);
+
+    let mut clouds = if args.planets {
+        load_system_planets(esi, &system).await?
+    } else {
+        load_system_asteroids(
+            esi,
+            &system,
+            &args.name_template,
+            prefetch,
+            cache,
+            &files.positions,
+            args.strict && system.space() == Space::Known,
+        )
+        .await?
+    };
+    if clouds.is_empty() {
+        let name = match system.space() {
+            Space::Known => system.name.clone(),
+            Space::Wormhole => format!("The wormhole system {}", system.name),
+            Space::Abyssal => format!("The Abyssal pocket {}", system.name),
+        };
+        let what = if args.planets {
+            "planets"
+        } else {
+            "asteroid belts"
+        };
+        eprintln!("{name} has no {what} to route");
+        if !args.planets && system.space() == Space::Wormhole {
+            eprintln!(
+                "Its ore is in the ore sites the ESI doesn't list, --planets routes its planets"
+            );
+        }
+    }
+    if let Some(gap) = args.gap_split {
+        clouds = clouds
+            .iter()
+            .flat_map(|cloud| cloud.split_by_gap(gap * 1000000.0))
+            .collect();
+    }
+    if let Some(ref whitelist) = files.whitelist {
+        clouds = clouds
+            .iter()
+            .map(|cloud| cloud.subcloud(whitelist))
+            .filter(|cloud| !cloud.is_empty())
+            .collect();
+    }
+    add_waypoints(&mut clouds, &args.add_points);
+    sort_clouds(&mut clouds, &args.order_by);
+    info!("Clouds: {}", clouds.len());
+    if args.format.contains(&Format::Tsplib) {
+        let dir = &args.tsplib_to;
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        for (n, cloud) in clouds.iter().enumerate() {
+            let path = dir.join(format!("{}_{n}.tsp", system.system_id));
+            std::fs::write(&path, cloud.to_tsplib())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            info!("Exported {}", path.display());
+        }
+    }
+    if let Some(ref dir) = args.dump_cloud {
+        for path in dump_clouds(dir, &system.system_id, &clouds)? {
+            info!("Dumped {}", path.display());
+        }
+    }
+
+    let imported = files.imported.as_ref();
+    let warp = WarpModel {
+        warp_speed: args.warp_speed,
+        align_time: args.align_time,
+    };
+    let deadline = args
+        .max_runtime
+        .map(|seconds| std::time::Instant::now() + Duration::from_secs_f64(seconds));
+    let ore = YieldModel {
+        ore_per_belt: args.ore_per_belt,
+        mining_rate: args.mining_rate,
+        ore_price: args.ore_price,
+    };
+    let options: Vec<RouteOptions> = clouds
+        .iter()
+        .map(|cloud| RouteOptions {
+            solver: args.solver,
+            chain: args.solver_chain.clone(),
+            polish: args.polish,
+            objective: args.minimize,
+            warp: warp.clone(),
+            coverage: args.coverage,
+            start: match args.route_from {
+                Some(ref key) => cloud.find(key),
+                None if args.from_center => cloud.nearest_to_centroid(),
+                None => None,
+            },
+            hint: args
+                .belt_order_hint
+                .iter()
+                .filter_map(|key| cloud.find(key))
+                .collect(),
+            through: args.route_through.as_ref().and_then(|key| cloud.find(key)),
+            brute_threshold: args.brute_threshold,
+            permutation_limit: args.permutation_limit,
+            slow_threshold: args.slow_threshold,
+            deadline,
+        })
+        .collect();
+    let route_cache = cache.zip(args.route_cache_ttl);
+    let mut fixed = Vec::new();
+    for (cloud, options) in clouds.iter().zip(&options) {
+        let route = match imported.and_then(|route| cloud.imported(route)) {
+            Some(route) => Some(route.with_context(|| {
+                format!(
+                    "The imported route doesn't fit the planet {}",
+                    cloud.planet_id
+                )
+            })?),
+            None => route_cache.and_then(|(cache, ttl)| {
+                // The hops routes visit the covering places only
+                let covering = cloud.covering(options);
+                let visited = covering.as_ref().unwrap_or(cloud);
+                fresh_route(cache, visited, &route_key(cloud, options), ttl)
+            }),
+        };
+        fixed.push(route);
+    }
+    let ndjson = args.format.contains(&Format::Ndjson);
+    let record = |report: &RouteReport| {
+        serde_json::to_string(&CloudRecord {
+            system_id: system.system_id,
+            system_name: &system.name,
+            report,
+        })
+    };
+    // The distance order is known once all the clouds are solved
+    let streamed = ndjson && args.order_by != CloudOrder::Distance;
+    let lines = OrderedLines::default();
+    let finish = |n: usize, cloud: &Cloud, options: &RouteOptions, (ordinal, shortest)| {
+        let mut report = RouteReport::new(cloud, &shortest, &ordinal);
+        if args.estimate_isk_per_hour {
+            let travel_time = warp.route_time(&report.hop_distances());
+            report.isk_per_hour = Some(ore.isk_per_hour(report.route.len(), travel_time));
+        }
+        report.slow = cloud.is_slow(args.slow_threshold);
+        // The bound of all the places doesn't hold for the hops skipping some
+        if (imported.is_some() || !cloud.is_exact(options)) && options.objective != Objective::Hops
+        {
+            report.lower_bound = Some(cloud.lower_bound());
+        }
+        if let Some(Endpoint::At(ref station)) = args.end_at_station {
+            report.station_distance = Some(cloud.station_leg(&shortest.1, station));
+        }
+        // Every cloud is written as soon as it and the clouds before it are routed
+        if streamed {
+            lines.push(n, record(&report)?, |line| println!("{line}"));
+        }
+        anyhow::Ok(((ordinal, shortest), report))
+    };
+    let (mut routes, mut reports): (Vec<(Route, Route)>, Vec<RouteReport>) =
+        solve_clouds(pool, &clouds, &options, &fixed, finish)
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+    // The routes cut short by the deadline are not kept
+    if let Some((cache, _)) = route_cache.filter(|_| deadline.is_none()) {
+        for (((cloud, options), fixed), (_, shortest)) in
+            clouds.iter().zip(&options).zip(&fixed).zip(&routes)
+        {
+            if fixed.is_none() {
+                cache.put_route(&route_key(cloud, options), &CachedRoute::new(&shortest.1))?;
+            }
+        }
+    }
+    // The clouds are ordered by the routes they are displayed with
+    if args.order_by == CloudOrder::Distance {
+        let order = distance_order(&routes);
+        clouds = reorder(clouds, &order);
+        routes = reorder(routes, &order);
+        reports = reorder(reports, &order);
+        if ndjson {
+            for report in &reports {
+                println!("{}", record(report)?);
+            }
+        }
+    }
+
+    if args.format.contains(&Format::Text) {
+        for line in system_lines(&clouds, &routes, &reports, args) {
+            println!("{line}");
+        }
+    }
+
+    Ok(SystemReport {
+        system_id: system.system_id,
+        name: system.name.clone(),
+        security_status: system.security_status,
+        clouds: reports,
+    })
+}
+
+/// Loads the systems and their belts which are not in the cache into it,
+/// returns the numbers of the cached systems and belts
+async fn prewarm(esi: &Esi, systems: &[i32], cache: &dyn Cache) -> anyhow::Result<(usize, usize)> {
+    let prefetch = Prefetch::load(esi, systems, Some(cache)).await?;
+    let belts = prefetch
+        .systems
+        .values()
+        .flat_map(|system| system.planets.iter().flatten())
+        .flat_map(|planet| planet.asteroid_belts.iter().flatten())
+        .filter(|id| cache.has_belt(id))
+        .count();
+    Ok((prefetch.systems.len(), belts))
+}
+
+/// Loads every system and its belts and prints the violations found, returns their number.
+/// The systems and the belts failing to load are the violations too.
+async fn audit(esi: &Esi, systems: &[i32], template: &NameTemplate) -> anyhow::Result<usize> {
+    let mut violations = 0;
+    for id in systems {
+        let (name, found) = match System::fetch(esi, id).await {
+            Ok(system) => {
+                let mut belts = Vec::new();
+                for planet in system.planets.iter().flatten() {
+                    for belt in planet.asteroid_belts.iter().flatten() {
+                        belts.push((*belt, AsteroidBelt::load(esi, belt).await));
+                    }
+                }
+                let found = audit_system(id, &system, &belts, template);
+                (system.name, found)
+            }
+            Err(err) => (
+                "Unknown".to_string(),
+                vec![format!("Failed to load the system {id}: {err:#}")],
+            ),
+        };
+        println!("{name} ({id}): {} violations", found.len());
+        for violation in &found {
+            println!("\t{violation}");
+        }
+        violations += found.len();
+    }
+    Ok(violations)
+}
+
+/// The inconsistent system payload, the belts failing to load, the belts of other systems,
+/// the names not matching the template, the shared positions and the distances
+/// which are not finite and positive
+fn audit_system(
+    id: &i32,
+    system: &System,
+    belts: &[(i32, anyhow::Result<AsteroidBelt>)],
+    template: &NameTemplate,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    if let Err(err) = system.validate(id) {
+        violations.push(err.to_string());
+    }
+    for (id, belt) in belts {
+        if let Err(err) = belt {
+            violations.push(format!("Failed to load the asteroid belt {id}: {err:#}"));
+        }
+    }
+    let belts = belts
+        .iter()
+        .filter_map(|(id, belt)| Some((*id, belt.as_ref().ok()?)))
+        .collect::<Vec<_>>();
+    for (id, belt) in &belts {
+        if belt.system_id != system.system_id {
+            violations.push(format!(
+                "The belt {id} belongs to the system {}",
+                belt.system_id
+            ));
+        }
+        if template.parse(&belt.name).is_none() {
+            violations.push(format!(
+                "The belt {id} `{}` doesn't match the name template",
+                belt.name
+            ));
+        }
+    }
+    for ((a, first), (b, second)) in belts.iter().tuple_combinations() {
+        let distance = Position::distance(&first.position, &second.position);
+        if first.position == second.position {
+            violations.push(format!("The belts {a} and {b} are at the same position"));
+        } else if !distance.is_finite() || distance <= 0.0 {
+            violations.push(format!("The distance between {a} and {b} is {distance}"));
+        }
+    }
+    violations
+}
+
+/// System ids in the order of the input, every system once.
+/// The inputs are either system ids or names resolved by the universe.
+/// Unknown inputs are skipped, or fail the resolution when strict.
+fn resolve_systems(
+    inputs: &[String],
+    universe: &Universe,
+    strict: bool,
+) -> anyhow::Result<Vec<i32>> {
+    let systems = universe.systems.clone().unwrap_or_default();
+    let mut seen = HashSet::new();
+    let mut resolved = Vec::new();
+    for input in inputs {
+        let id = input.parse::<i32>().ok().or_else(|| {
+            systems
+                .iter()
+                .find(|obj| obj.name.eq_ignore_ascii_case(input))
+                .map(|obj| obj.id)
+        });
+        match id {
+            Some(id) if seen.insert(id) => resolved.push(id),
+            Some(_) => {}
+            None if strict => anyhow::bail!("Unknown system: {input}"),
+            None => warn!("Unknown system: {input}"),
+        }
+    }
+    Ok(resolved)
+}
+
+/// The reports of the JSON output, the schema versions are checked before the reports
+/// are parsed so a newer schema is told apart from a broken file
+fn parse_reports(text: &str) -> anyhow::Result<Vec<SystemReport>> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    let versions = value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|system| system["clouds"].as_array())
+        .flatten()
+        .map(|cloud| cloud["schema_version"].as_u64().unwrap_or(0));
+    if let Some(version) = versions
+        .max()
+        .filter(|version| *version > REPORT_VERSION.into())
+    {
+        anyhow::bail!(
+            "The report has the schema version {version}, this build reads up to {REPORT_VERSION}"
+        );
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Writes the text to the file or to the standard output for `-`
+fn write_output(path: &str, text: &str) -> anyhow::Result<()> {
+    if "-" == path {
+        println!("{text}");
+    } else {
+        std::fs::write(path, format!("{text}\n"))
+            .with_context(|| format!("Failed to write {path}"))?;
+    }
+    Ok(())
+}
+
+fn format_error(err: &anyhow::Error, verbose: bool) -> String {
+    if verbose {
+        let mut text = String::from("Error chain:");
+        for (i, cause) in err.chain().enumerate() {
+            text += &format!("\n{i:>4}: {cause}\n      {cause:?}");
+        }
+        text
+    } else {
+        format!("Error: {err:#}")
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+    let mut args = Args::parse();
+    args.color = args.color.resolve(args.no_color);
+    if args.planet_order {
+        args.order_by = CloudOrder::Planet;
+    }
+
+    if let Err(err) = run(&args).await {
+        eprintln!("{}", format_error(&err, args.verbose_errors));
+        std::process::exit(1);
+    }
+}
+
+/// Worked invocations with their descriptions
+const EXAMPLES: &[(&str, &str)] = &[
+    ("Tanoo", "Route the asteroid belts of a single system"),
+    (
+        "Tanoo Jita 30000142",
+        "Route several systems by names or ids",
+    ),
+    (
+        "--format json --json-to routes.json Tanoo",
+        "Write the routes of the system as JSON into a file",
+    ),
+    (
+        "--format text --format json Tanoo",
+        "Print the warp instructions and the JSON together",
+    ),
+    ("--planets Tanoo", "Route the planets instead of the belts"),
+    (
+        "--solver lk --route-from \"Tanoo I - Asteroid Belt 1\" Tanoo",
+        "Start from the given belt and improve the route by Lin-Kernighan",
+    ),
+    ("--resolve-only Tanoo", "Show what the names resolve to"),
+    (
+        "--watch 300 Tanoo",
+        "Route the system again every five minutes",
+    ),
+];
+
+fn examples() -> String {
+    let name = env!("CARGO_PKG_NAME");
+    EXAMPLES
+        .iter()
+        .map(|(args, about)| format!("{about}:\n    {name} {args}\n"))
+        .join("\n")
+}
+
+/// Changes an option of the interactive mode
+fn apply_setting(args: &mut Args, option: &str, value: &str) -> anyhow::Result<()> {
+    match option {
+        "solver" => args.solver = ValueEnum::from_str(value, true).map_err(anyhow::Error::msg)?,
+        "minimize" => {
+            args.minimize = ValueEnum::from_str(value, true).map_err(anyhow::Error::msg)?
+        }
+        "coverage" => args.coverage = value.parse()?,
+        "number-style" => {
+            args.number_style = ValueEnum::from_str(value, true).map_err(anyhow::Error::msg)?
+        }
+        "precision" => args.precision = value.parse()?,
+        "order-by" => {
+            args.order_by = ValueEnum::from_str(value, true).map_err(anyhow::Error::msg)?
+        }
+        "route-from" if value == "none" => args.route_from = None,
+        "route-from" => args.route_from = Some(value.to_string()),
+        "planets" => args.planets = value.parse()?,
+        _ => anyhow::bail!("Unknown option `{option}`"),
+    }
+    Ok(())
+}
+
+/// Routes the system of every line of the input until `quit` or the end of it
+async fn repl(
+    esi: &Esi,
+    pool: &rayon::ThreadPool,
+    cache: Option<&dyn Cache>,
+    files: &BeltFiles,
+    args: &Args,
+    mut input: impl std::io::BufRead,
+    prompt: bool,
+) -> anyhow::Result<()> {
+    let mut args = args.clone();
+    let mut line = String::new();
+    loop {
+        if prompt {
+            eprint!("> ");
+        }
+        line.clear();
+        if 0 == input.read_line(&mut line)? {
+            break;
+        }
+        let query = line.trim();
+        let words = query.split_whitespace().collect::<Vec<&str>>();
+        let done = match words.as_slice() {
+            [] => Ok(()),
+            ["quit"] | ["exit"] => break,
+            ["set", option, value @ ..] if !value.is_empty() => {
+                apply_setting(&mut args, option, &value.join(" "))
+            }
+            ["set", ..] => Err(anyhow::anyhow!("Usage: set OPTION VALUE")),
+            _ => {
+                let names = vec![query.to_string()];
+                let universe = if query.parse::<i32>().is_ok() {
+                    Ok(Universe::default())
+                } else {
+                    Universe::load(esi, &names).await
+                };
+                match universe {
+                    Ok(universe) => match resolve_systems(&names, &universe, args.strict) {
+                        Ok(systems) if systems.is_empty() => {
+                            Err(anyhow::anyhow!("Unknown system: {query}"))
+                        }
+                        Ok(systems) => {
+                            route_systems(esi, pool, cache, files, &systems, &args).await
+                        }
+                        Err(err) => Err(err),
+                    },
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        if let Err(err) = done {
+            eprintln!("{}", format_error(&err, args.verbose_errors));
+        }
+    }
+    Ok(())
+}
+
+async fn run(args: &Args) -> anyhow::Result<()> {
+    if args.examples {
+        print!("{}", examples());
+    } else if args.interactive {
+        let esi = Esi::from_args(args);
+        let pool = thread_pool(args.threads)?;
+        let cache = open_cache(args)?;
+        let files = BeltFiles::read(args)?;
+        let prompt = std::io::stdin().is_terminal();
+        let input = std::io::stdin().lock();
+        repl(&esi, &pool, cache.as_deref(), &files, args, input, prompt).await?;
+    } else if args.names.is_empty() {
+        Args::command().print_help()?;
+    } else {
+        let esi = Esi::from_args(args);
+        let pool = thread_pool(args.threads)?;
+        // A broken file fails before any system is loaded
+        let files = BeltFiles::read(args)?;
+        // The names resolved before are taken by their ids, --resolve-only asks the ESI anyway
+        let cache = open_cache(args)?.filter(|_| !args.resolve_only);
+        let mut inputs = args.names.clone();
+        if let Some(ref cache) = cache {
+            for input in inputs.iter_mut() {
+                if let Some(id) = cache.system_id(input) {
+                    *input = id.to_string();
+                }
+            }
+        }
+        let names = inputs
+            .iter()
+            .filter(|name| name.parse::<i32>().is_err())
+            .cloned()
+            .collect::<Vec<String>>();
+        let universe = if names.is_empty() {
+            Universe::default()
+        } else {
+            Universe::load(&esi, &names).await?
+        };
+        if let Some(ref cache) = cache {
+            for system in universe.systems.iter().flatten() {
+                cache.put_system_id(&system.name, &system.id)?;
+            }
+        }
+        if args.fuzzy {
+            let known = universe.systems.clone().unwrap_or_default();
+            for input in inputs
+                .iter_mut()
+                .filter(|name| name.parse::<i32>().is_err())
+            {
+                if known.iter().any(|obj| obj.name.eq_ignore_ascii_case(input)) {
+                    continue;
+                }
+                let candidates = Search::systems(&esi, input).await?;
+                let mut stdin = std::io::stdin().lock();
+                let prompt = std::io::stdin()
+                    .is_terminal()
+                    .then_some(&mut stdin as &mut dyn std::io::BufRead);
+                if let Some(id) = pick_candidate(input, &candidates, prompt)? {
+                    *input = id.to_string();
+                }
+            }
+        }
+
+        if args.resolve_only {
+            print!("{universe}");
+        } else if args.prewarm {
+            let systems = resolve_systems(&inputs, &universe, args.strict)?;
+            let cache = cache.context("--prewarm needs --cache")?;
+            let (systems, belts) = prewarm(&esi, &systems, cache.as_ref()).await?;
+            eprintln!("Cached {systems} systems and {belts} belts");
+        } else if args.audit {
+            let systems = resolve_systems(&inputs, &universe, args.strict)?;
+            let violations = audit(&esi, &systems, &args.name_template).await?;
+            if violations > 0 {
+                anyhow::bail!("The audit found {violations} violations");
+            }
+        } else {
+            let mut systems = resolve_systems(&inputs, &universe, args.strict)?;
+            if let Some(limit) = args.limit.filter(|limit| *limit < systems.len()) {
+                eprintln!(
+                    "Routing {limit} of {} systems, {} skipped",
+                    systems.len(),
+                    systems.len() - limit
+                );
+                systems.truncate(limit);
+            }
+            match args.watch {
+                Some(seconds) => {
+                    let runs = watch(
+                        Duration::from_secs(seconds),
+                        async {
+                            tokio::signal::ctrl_c().await.ok();
+                        },
+                        || route_systems(&esi, &pool, cache.as_deref(), &files, &systems, args),
+                        |err| eprintln!("{}", format_error(&err, args.verbose_errors)),
+                    )
+                    .await;
+                    eprintln!("Stopped after {runs} runs");
+                }
+                None => {
+                    route_systems(&esi, &pool, cache.as_deref(), &files, &systems, args).await?
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn route_systems(
+    esi: &Esi,
+    pool: &rayon::ThreadPool,
+    cache: Option<&dyn Cache>,
+    files: &BeltFiles,
+    systems: &[i32],
+    args: &Args,
+) -> anyhow::Result<()> {
+    // The station is loaded once for all the systems
+    let resolved;
+    let args = match args.end_at_station {
+        Some(Endpoint::Station(id)) => {
+            let station = Station::load(esi, &id)
+                .await
+                .with_context(|| format!("Failed to load the station {id}"))?;
+            info!("station: {}", station.name);
+            resolved = Args {
+                end_at_station: Some(Endpoint::At(station.position)),
+                ..args.clone()
+            };
+            &resolved
+        }
+        _ => args,
+    };
+    let prefetch = if args.prefetch_belt_ids && !args.planets {
+        Prefetch::load(esi, systems, cache).await?
+    } else {
+        Prefetch::default()
+    };
+    let mut reports = Vec::new();
+    for id in systems {
+        info!("id: {id}");
+        reports.push(make_route(esi, pool, cache, files, id, args, &prefetch).await?);
+    }
+    // The earlier report may be overwritten by the new one
+    if let Some(path) = &args.diff {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let old =
+            parse_reports(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        println!("Changes against {}:", path.display());
+        for line in diff_reports(&old, &reports, &args.number_style, args.precision) {
+            println!("{line}");
+        }
+    }
+    if args.compare_systems {
+        let lines = ranking_lines(
+            &reports,
+            &args.score_weights,
+            &args.number_style,
+            args.precision,
+        );
+        for line in lines {
+            println!("{line}");
+        }
+    }
+    if args.format.contains(&Format::Json) {
+        write_output(&args.json_to, &serde_json::to_string_pretty(&reports)?)?;
+    }
+    if let Some(path) = &args.summary_csv {
+        write_output(path, &summary_csv(&reports))?;
+    }
+    if args.format.contains(&Format::Dot) {
+        write_output(
+            &args.dot_to,
+            &dot_graph(&reports, &args.number_style, args.precision),
+        )?;
+    }
+    if args.format.contains(&Format::Bookmarks) {
+        let bookmarks = Bookmark::from_reports(&reports);
+        write_output(
+            &args.bookmarks_to,
+            &serde_json::to_string_pretty(&bookmarks)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs the job at once and then every interval until the shutdown completes.
+/// A running job is finished before the shutdown, a failed one is passed to `failed`
+/// and the next tick is waited for. Returns the number of the runs.
+async fn watch<F, J>(
+    interval: Duration,
+    shutdown: impl std::future::Future<Output = ()>,
+    mut job: F,
+    mut failed: impl FnMut(anyhow::Error),
+) -> usize
+where
+    F: FnMut() -> J,
+    J: std::future::Future<Output = anyhow::Result<()>>,
+{
+    tokio::pin!(shutdown);
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut runs = 0;
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = ticks.tick() => {
+                if let Err(err) = job().await {
+                    failed(err);
+                }
+                runs += 1;
+            }
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    /// Answers the requests by the canned responses of their paths without any socket.
+    /// The responses of a path are given in turn, the last one is repeated.
+    #[derive(Debug, Default)]
+    struct FakeHttp {
+        responses: std::sync::Mutex<HashMap<String, std::collections::VecDeque<HttpResponse>>>,
+        requests: std::sync::Mutex<Vec<HttpRequest>>,
+    }
+    impl FakeHttp {
+        fn respond(self, path: &str, status: u16, body: &str) -> Self {
+            self.responses
+                .lock()
+                .unwrap()
+                .entry(path.to_string())
+                .or_default()
+                .push_back(HttpResponse {
+                    status,
+                    body: body.to_string(),
+                });
+            self
+        }
+    }
+    impl HttpClient for FakeHttp {
+        fn send(&self, request: HttpRequest) -> HttpFuture<'_> {
+            let path = request
+                .url
+                .trim_start_matches("http://esi.fake")
+                .split('?')
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            self.requests.lock().unwrap().push(request);
+            let mut responses = self.responses.lock().unwrap();
+            let response = match responses.get_mut(&path) {
+                Some(queue) if queue.len() > 1 => queue.pop_front(),
+                Some(queue) => queue.front().cloned(),
+                None => None,
+            };
+            let response = response.unwrap_or(HttpResponse {
+                status: 404,
+                body: String::new(),
+            });
+            Box::pin(async move { Ok(response) })
+        }
+    }
+
+    /// The cloud of the planet with a place named `System I - Asteroid Belt {id}`
+    /// at every id and x, y, z coordinates
+    fn cloud_of(planet: i32, places: &[(i32, f64, f64, f64)]) -> Cloud {
+        let mut cloud = Cloud::with_planet(&planet);
+        for (id, x, y, z) in places {
+            cloud.add(
+                id,
+                &format!("System I - Asteroid Belt {id}"),
+                &Position::new(x, y, z),
+            );
+        }
+        cloud
+    }
+
+    #[test]
+    fn test_cloud() {
+        let mut cloud = Cloud::new();
+        assert_eq!(cloud.distance_between(&0, &1), None);
+
+        cloud.add(
+            &1,
+            &String::from("System I - Asteroid Belt 1"),
+            &Position::new(&0.0, &0.0, &0.0),
+        );
+        cloud.add(
+            &2,
+            &String::from("System I - Asteroid Belt 2"),
+            &Position::new(&1.0, &0.0, &0.0),
+        );
+
+        assert_eq!(cloud.distance_between(&1, &2), Some(1.0));
+        assert_eq!(cloud.distance_between(&2, &1), Some(1.0));
+    }
+
+    #[test]
+    fn test_cloud_get_best_route() {
+        let mut cloud = Cloud::new();
+        assert_eq!((0.0, vec![]), cloud.get_best_route());
+
+        cloud.add(
+            &1,
+            &String::from("System I - Asteroid Belt 1"),
+            &Position::new(&0.0, &0.0, &0.0),
+        );
+        assert_eq!((0.0, vec![1]), cloud.get_best_route());
+
+        cloud.add(
+            &2,
+            &String::from("System I - Asteroid Belt 2"),
+            &Position::new(&1.0, &0.0, &0.0),
+        );
+        assert_eq!(1.0, cloud.get_best_route().0);
+
+        cloud.add(
+            &3,
+            &String::from("System I - Asteroid Belt 3"),
+            &Position::new(&2.0, &0.0, &0.0),
+        );
+        assert_eq!(2.0, cloud.get_best_route().0);
+
+        cloud.add(
+            &4,
+            &String::from("System I - Asteroid Belt 4"),
+            &Position::new(&3.0, &0.0, &0.0),
+        );
+        assert_eq!(3.0, cloud.get_best_route().0);
+    }
+
+    #[test]
+    fn test_asteroid_belt_fixture() {
+        let belt: AsteroidBelt =
+            serde_json::from_str(include_str!("../tests/fixtures/asteroid_belt.json")).unwrap();
+        assert_eq!("Tanoo I - Asteroid Belt 1", belt.name);
+        assert_eq!(30000001, belt.system_id);
+        assert_eq!(
+            Position::new(&161366522880.0, &35191971840.0, &-70176645120.0),
+            belt.position
+        );
+    }
+
+    #[test]
+    fn test_system_fixture() {
+        let system: System =
+            serde_json::from_str(include_str!("../tests/fixtures/system.json")).unwrap();
+        assert_eq!("Tanoo", system.name);
+        assert_eq!(30000001, system.system_id);
+        assert_eq!(20000001, system.constellation_id);
+        assert!((system.security_status - 0.858324).abs() < 1e-6);
+
+        let planets = system.planets.unwrap();
+        assert_eq!(3, planets.len());
+        assert_eq!(40000002, planets[0].planet_id);
+        assert_eq!(Some(vec![40000003]), planets[0].asteroid_belts);
+        assert_eq!(Some(vec![40000004]), planets[0].moons);
+        assert_eq!(None, planets[1].asteroid_belts);
+        assert_eq!(None, planets[1].moons);
+        assert_eq!(Some(vec![40000008, 40000009]), planets[2].asteroid_belts);
+    }
+
+    #[test]
+    fn test_universe_fixture() {
+        let universe: Universe =
+            serde_json::from_str(include_str!("../tests/fixtures/universe_ids.json")).unwrap();
+        let systems = universe.systems.unwrap();
+        assert_eq!(2, systems.len());
+        assert_eq!(30000001, systems[0].id);
+        assert_eq!("Tanoo", systems[0].name);
+        assert_eq!(30000142, systems[1].id);
+        assert_eq!("Jita", systems[1].name);
+
+        let regions = universe.regions.unwrap();
+        assert_eq!(10000001, regions[0].id);
+        assert_eq!("Derelik", regions[0].name);
+        assert_eq!(1, universe.constellations.unwrap().len());
+        assert_eq!(None, universe.agents);
+        assert_eq!(None, universe.inventory_types);
+    }
+
+    #[test]
+    fn test_esi_url() {
+        assert_eq!(
+            format!("{ROOT}/universe/systems/1/{PARAM}"),
+            Esi::default().url("universe/systems/1")
+        );
+        assert_eq!(
+            format!("http://localhost:8080/universe/ids/{PARAM}"),
+            Esi::new("http://localhost:8080/").url("universe/ids")
+        );
+    }
+
+    #[test]
+    fn test_cloud_nearest_insertion() {
+        // Belts on a half circle: the optimal route walks along the arc
+        let mut cloud = Cloud::new();
+        for i in 1..=7 {
+            let angle = std::f64::consts::PI * (i as f64) / 8.0;
+            cloud.add(
+                &i,
+                &format!("System I - Asteroid Belt {i}"),
+                &Position::new(&angle.cos(), &angle.sin(), &0.0),
+            );
+        }
+
+        let (distance, route) = cloud.get_route(&Solver::NearestInsertion);
+        let mut sorted = route.clone();
+        sorted.sort();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7], sorted);
+
+        let (optimal, _) = cloud.get_route(&Solver::Size);
+        assert!((distance - optimal).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fmt() {
+        assert_eq!("0 Mm", fmt(&0.0, &NumberStyle::Plain, 0));
+        assert_eq!("955 Mm", fmt(&955_400_000.0, &NumberStyle::Plain, 0));
+        assert_eq!("955 Mm", fmt(&955_400_000.0, &NumberStyle::Grouped, 0));
+        assert_eq!("955 Mm", fmt(&955_400_000.0, &NumberStyle::Scaled, 0));
+
+        assert_eq!("1240 Mm", fmt(&1_240_000_000.0, &NumberStyle::Plain, 0));
+        assert_eq!("1,240 Mm", fmt(&1_240_000_000.0, &NumberStyle::Grouped, 0));
+        assert_eq!("1.24 Gm", fmt(&1_240_000_000.0, &NumberStyle::Scaled, 0));
+
+        let region = 1_240_000_000_000_000.0;
+        assert_eq!("1240000000 Mm", fmt(&region, &NumberStyle::Plain, 0));
+        assert_eq!("1,240,000,000 Mm", fmt(&region, &NumberStyle::Grouped, 0));
+        assert_eq!("1240000.00 Gm", fmt(&region, &NumberStyle::Scaled, 0));
+
+        let hop = 2_345_678.0;
+        assert_eq!("2 Mm", fmt(&hop, &NumberStyle::Plain, 0));
+        assert_eq!("2.3 Mm", fmt(&hop, &NumberStyle::Plain, 1));
+        assert_eq!("2.346 Mm", fmt(&hop, &NumberStyle::Plain, 3));
+        assert_eq!("3 Mm", fmt(&2_500_000.0, &NumberStyle::Plain, 0));
+        assert_eq!(
+            "1,240,000.46 Mm",
+            fmt(&1_240_000_456_789.0, &NumberStyle::Grouped, 2)
+        );
+        assert_eq!("1.240 Gm", fmt(&1_240_000_000.0, &NumberStyle::Scaled, 3));
+        assert_eq!("2.35 Mm", fmt(&hop, &NumberStyle::Scaled, 2));
+
+        // The close belts are not at the same position
+        for style in [
+            NumberStyle::Plain,
+            NumberStyle::Grouped,
+            NumberStyle::Scaled,
+        ] {
+            assert_eq!("400 km", fmt(&400_000.0, &style, 0));
+        }
+        assert_eq!("0.4 Mm", fmt(&400_000.0, &NumberStyle::Plain, 1));
+        assert_eq!("40 km", fmt(&40_000.0, &NumberStyle::Plain, 1));
+        assert_eq!("-400 km", fmt(&-400_000.0, &NumberStyle::Plain, 0));
+        assert_eq!("120 m", fmt(&120.0, &NumberStyle::Plain, 0));
+        assert_eq!("<1 m", fmt(&0.3, &NumberStyle::Plain, 0));
+        assert_eq!("<1 m", fmt(&1e-6, &NumberStyle::Scaled, 2));
+        assert_eq!("1 Mm", fmt(&500_000.0, &NumberStyle::Plain, 0));
+
+        // The hops, the total and the summary lines alike
+        let mut cloud = Cloud::new();
+        cloud.add(&1, "Tanoo I - Asteroid Belt 1", &Position::default());
+        cloud.add(
+            &2,
+            "Tanoo I - Asteroid Belt 2",
+            &Position::new(&hop, &0.0, &0.0),
+        );
+        let route = (hop, vec![1, 2]);
+        let report = RouteReport::new(&cloud, &route, &(2.0 * hop, vec![]));
+        let args = Args::parse_from(["best_route", "--precision", "1", "Tanoo"]);
+        let lines = system_lines(
+            std::slice::from_ref(&cloud),
+            &[((2.0 * hop, vec![]), route)],
+            &[report],
+            &args,
+        );
+        assert!(lines
+            .iter()
+            .any(|line| line.ends_with(" - 2.3 Mm (cum 2.3 Mm)")));
+        assert!(lines.contains(&"The length of the route: 2.3 Mm".to_string()));
+        assert!(lines.contains(&"Saved 50% vs ordinal (4.7 Mm → 2.3 Mm)".to_string()));
+    }
+
+    #[test]
+    fn test_cloud_route_is_optimal() {
+        let mut cloud = Cloud::new();
+        for i in 1..=4 {
+            cloud.add(
+                &i,
+                &format!("System I - Asteroid Belt {i}"),
+                &Position::new(&(i as f64), &0.0, &0.0),
+            );
+        }
+        assert_eq!(Some(true), cloud.route_is_optimal(&[1, 2, 3, 4]));
+        assert_eq!(Some(true), cloud.route_is_optimal(&[4, 3, 2, 1]));
+        assert_eq!(Some(false), cloud.route_is_optimal(&[1, 3, 2, 4]));
+
+        for i in 5..=10 {
+            cloud.add(
+                &i,
+                &format!("System I - Asteroid Belt {i}"),
+                &Position::new(&(i as f64), &0.0, &0.0),
+            );
+        }
+        assert_eq!(None, cloud.route_is_optimal(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_universe_display() {
+        let universe: Universe =
+            serde_json::from_str(include_str!("../tests/fixtures/universe_ids.json")).unwrap();
+        assert_eq!(
+            "constellations:\n\
+             \t20000001 - San Matar\n\
+             regions:\n\
+             \t10000001 - Derelik\n\
+             systems:\n\
+             \t30000001 - Tanoo\n\
+             \t30000142 - Jita\n",
+            universe.to_string()
+        );
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        assert!(approx_eq(0.0, 0.0));
+        assert!(approx_eq(0.1 + 0.2, 0.3));
+        assert!(approx_eq(2999999.999804803, 2999999.999057624));
+        assert!(!approx_eq(1.0, 1.001));
+        assert!(is_shorter(1.0, 1.001));
+        assert!(!is_shorter(0.3, 0.1 + 0.2));
+    }
+
+    #[test]
+    fn test_cloud_near_equal_routes() {
+        // A rotated square: every walk along its sides has the same length
+        // up to the rounding of the coordinates
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 69486.747, 52754.924, 0.0),
+                (2, 734109.548, 799933.974, 0.0),
+                (3, -13069.501, 1464556.775, 0.0),
+                (4, -677692.302, 717377.725, 0.0),
+            ],
+        );
+
+        let (distance, route) = cloud.get_route(&Solver::Size);
+        assert_eq!(vec![1, 2, 3, 4], route);
+        assert!(approx_eq(3000000.0, distance));
+        assert_eq!(Some(true), cloud.route_is_optimal(&[1, 4, 3, 2]));
+    }
+
+    #[test]
+    fn test_cloud_of_planets() {
+        let mut cloud = Cloud::new();
+        let planets = [
+            (40000002, "Tanoo I", 0.0),
+            (40000005, "Tanoo II", 3.0),
+            (40000007, "Tanoo III", 1.0),
+            (40000010, "Tanoo IV", 2.0),
+        ];
+        for (id, name, x) in planets {
+            cloud.add_place(Place::named(&id, name, &Position::new(&x, &0.0, &0.0)));
+        }
+        assert_eq!(Some(String::from("Tanoo III")), cloud.get_name(&40000007));
+
+        let (distance, route) = cloud.get_ordinal_route();
+        assert_eq!(vec![40000002, 40000005, 40000007, 40000010], route);
+        assert_eq!(6.0, distance);
+
+        let (distance, route) = cloud.get_best_route();
+        assert_eq!(vec![40000002, 40000007, 40000010, 40000005], route);
+        assert_eq!(3.0, distance);
+    }
+
+    #[test]
+    fn test_retry_delay_jitter() {
+        use rand::SeedableRng;
+
+        let retry = Retry {
+            attempts: 5,
+            delay: Duration::from_millis(100),
+            jitter: 0.5,
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let delays = (0..5)
+            .map(|attempt| retry.delay(attempt, &mut rng))
+            .collect::<Vec<Duration>>();
+        for (attempt, delay) in delays.iter().enumerate() {
+            let backoff = 100.0 * 2f64.powi(attempt as i32);
+            let millis = delay.as_secs_f64() * 1000.0;
+            assert!(backoff * 0.5 <= millis && millis <= backoff * 1.5);
+        }
+
+        // The same seed gives the same delays, the jitter spreads them
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        assert_eq!(delays[0], retry.delay(0, &mut rng));
+        assert_ne!(Duration::from_millis(100), delays[0]);
+
+        let steady = Retry {
+            jitter: 0.0,
+            ..retry
+        };
+        assert_eq!(Duration::from_millis(400), steady.delay(2, &mut rng));
+    }
+
+    #[test]
+    fn test_sort_clouds() {
+        // (planet, roman, belt positions)
+        let layout = [
+            (40000007, "III", vec![0.0, 1.0]),
+            (40000002, "I", vec![0.0, 5.0, 7.0]),
+            (40000010, "IV", vec![0.0]),
+            (40000005, "II", vec![0.0, 2.0]),
+        ];
+        let mut clouds = Vec::new();
+        for (planet_id, roman, positions) in &layout {
+            let mut cloud = Cloud::with_planet(planet_id);
+            for (i, x) in positions.iter().enumerate() {
+                let id = planet_id + i as i32 + 1;
+                let name = format!("System {roman} - Asteroid Belt {}", i + 1);
+                cloud.add(&id, &name, &Position::new(x, &0.0, &0.0));
+            }
+            clouds.push(cloud);
+        }
+        let planets =
+            |clouds: &Vec<Cloud>| clouds.iter().map(|c| c.planet_id).collect::<Vec<i32>>();
+
+        sort_clouds(&mut clouds, &CloudOrder::Planet);
+        assert_eq!(
+            vec![40000002, 40000005, 40000007, 40000010],
+            planets(&clouds)
+        );
+
+        sort_clouds(&mut clouds, &CloudOrder::Belts);
+        assert_eq!(
+            vec![40000010, 40000005, 40000007, 40000002],
+            planets(&clouds)
+        );
+
+        let solved = clouds
+            .iter()
+            .map(|cloud| (cloud.get_ordinal_route(), cloud.get_route(&Solver::Size)))
+            .collect::<Vec<_>>();
+        let order = distance_order(&solved);
+        assert_eq!(
+            vec![40000010, 40000007, 40000005, 40000002],
+            planets(&reorder(clouds.clone(), &order))
+        );
+        assert_eq!(solved[order[1]], reorder(solved.clone(), &order)[1]);
+
+        // By the planet until the routes are solved
+        sort_clouds(&mut clouds, &CloudOrder::Distance);
+        assert_eq!(
+            vec![40000002, 40000005, 40000007, 40000010],
+            planets(&clouds)
+        );
+
+        sort_clouds(&mut clouds, &CloudOrder::Name);
+        assert_eq!(
+            vec![40000002, 40000005, 40000007, 40000010],
+            planets(&clouds)
+        );
+        assert_eq!("System I - Asteroid Belt 1", clouds[0].name());
+    }
+
+    #[test]
+    fn test_cloud_nearest_neighbor_of() {
+        let cloud = cloud_of(0, &[(1, 0.0, 0.0, 0.0)]);
+        assert_eq!(None, cloud.nearest_neighbor_of(&1));
+
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 1.0, 0.0, 0.0),
+                (3, 3.0, 0.0, 0.0),
+                (4, 6.0, 0.0, 0.0),
+            ],
+        );
+        assert_eq!(Some((2, 1.0)), cloud.nearest_neighbor_of(&1));
+        assert_eq!(Some((1, 1.0)), cloud.nearest_neighbor_of(&2));
+        assert_eq!(Some((2, 2.0)), cloud.nearest_neighbor_of(&3));
+        assert_eq!(Some((3, 3.0)), cloud.nearest_neighbor_of(&4));
+        assert_eq!(None, cloud.nearest_neighbor_of(&5));
+    }
+
+    #[test]
+    fn test_route_report() {
+        let cloud = cloud_of(
+            40000002,
+            &[(1, 0.0, 0.0, 0.0), (2, 3.0, 0.0, 0.0), (3, 1.0, 0.0, 0.0)],
+        );
+        let report = RouteReport::new(&cloud, &cloud.get_best_route(), &cloud.get_ordinal_route());
+        assert_eq!(40000002, report.planet_id);
+        assert_eq!(3.0, report.distance);
+        assert_eq!(5.0, report.ordinal_distance);
+        assert_eq!(
+            vec![(1, 0.0), (3, 1.0), (2, 2.0)],
+            report
+                .route
+                .iter()
+                .map(|hop| (hop.id, hop.distance))
+                .collect::<Vec<(i32, f64)>>()
+        );
+        assert_eq!("System I - Asteroid Belt 3", report.route[1].name);
+    }
+
+    #[test]
+    fn test_isk_per_hour() {
+        let ore = YieldModel {
+            ore_per_belt: 10000.0,
+            mining_rate: 10.0,
+            ore_price: 100.0,
+        };
+        // 3 belts: 3M ISK of ore, mined in 3000 s plus 600 s of travel
+        assert_eq!(3_000_000.0, ore.isk_per_hour(3, 600.0));
+        assert_eq!(3_600_000.0, ore.isk_per_hour(3, 0.0));
+        assert_eq!(0.0, ore.isk_per_hour(0, 0.0));
+
+        let warp = WarpModel {
+            warp_speed: 3.0,
+            align_time: 5.0,
+        };
+        assert_eq!(0.0, warp.hop_time(0.0));
+        let short = warp.hop_time(10_000_000.0);
+        let long = warp.hop_time(10.0 * AU);
+        assert!(5.0 < short && short < long);
+        // A long warp cruises at 3 AU/s
+        assert!((warp.hop_time(20.0 * AU) - long - 10.0 / 3.0).abs() < 1e-6);
+        assert_eq!(short + long, warp.route_time(&[10_000_000.0, 10.0 * AU]));
+
+        let parse = |flag: &str| Args::try_parse_from(["best_route", flag, "Tanoo"]);
+        let args = parse("--align-time=0").unwrap();
+        assert_eq!((3.0, 0.0), (args.warp_speed, args.align_time));
+        for (flag, error) in [
+            ("--warp-speed=0", "`0` is not a positive number"),
+            ("--warp-speed=NaN", "`NaN` is not a positive number"),
+            ("--align-time=-1", "`-1` is not a non-negative number"),
+        ] {
+            let err = parse(flag).unwrap_err().to_string();
+            assert!(err.contains(error), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_systems() {
+        let universe: Universe =
+            serde_json::from_str(include_str!("../tests/fixtures/universe_ids.json")).unwrap();
+        let inputs = ["Jita", "Tanoo", "jita", "30000001", "Unknown", "30000002"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            vec![30000142, 30000001, 30000002],
+            resolve_systems(&inputs, &universe, false).unwrap()
+        );
+        assert!(resolve_systems(&[], &universe, false).unwrap().is_empty());
+        assert!(resolve_systems(&inputs, &universe, true).is_err());
+    }
+
+    #[test]
+    fn test_cloud_split_by_gap() {
+        let cloud = cloud_of(
+            40000002,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 1.0, 0.0, 0.0),
+                (3, 2.0, 0.0, 0.0),
+                (4, 100.0, 0.0, 0.0),
+                (5, 101.5, 0.0, 0.0),
+            ],
+        );
+
+        let groups = cloud.split_by_gap(10.0);
+        assert_eq!(2, groups.len());
+        assert_eq!(vec![1, 2, 3], groups[0].get_ids_sorted_by_name());
+        assert_eq!(vec![4, 5], groups[1].get_ids_sorted_by_name());
+        assert_eq!(Some(1.5), groups[1].distance_between(&4, &5));
+        assert_eq!(None, groups[1].distance_between(&3, &4));
+        assert!(groups.iter().all(|group| group.planet_id == 40000002));
+
+        assert_eq!(1, cloud.split_by_gap(1000.0).len());
+        assert_eq!(5, cloud.split_by_gap(0.5).len());
+        assert!(Cloud::new().split_by_gap(10.0).is_empty());
+    }
+
+    #[test]
+    fn test_cloud_serde() {
+        let cloud = cloud_of(
+            40000002,
+            &[
+                (1, 0.1, 0.2, 0.3),
+                (2, 1.0, -7.7, 3.3),
+                (3, -1e11, 2e10, 5.5e9),
+            ],
+        );
+
+        let json = serde_json::to_string(&cloud).unwrap();
+        let restored: Cloud = serde_json::from_str(&json).unwrap();
+        assert_eq!(cloud, restored);
+        assert_eq!(1, restored.places[&3].cloud_number);
+        assert_eq!(cloud.get_best_route(), restored.get_best_route());
+    }
+
+    #[test]
+    fn test_format_error() {
+        let source = std::io::Error::other("connection reset");
+        let err = anyhow::Error::from(source)
+            .context("Failed to load the asteroid belt 1")
+            .context("Failed to load the system 2");
+
+        assert_eq!(
+            "Error: Failed to load the system 2: Failed to load the asteroid belt 1: connection reset",
+            format_error(&err, false)
+        );
+
+        let verbose = format_error(&err, true);
+        assert!(verbose.starts_with("Error chain:\n   0: Failed to load the system 2\n"));
+        assert!(verbose.contains("\n   1: Failed to load the asteroid belt 1\n"));
+        assert!(verbose.contains("\n   2: connection reset\n"));
+        assert!(verbose.contains(r#"Custom { kind: Other, error: "connection reset" }"#));
+    }
+
+    #[test]
+    fn test_cloud_len() {
+        let cloud = Cloud::new();
+        assert_eq!(0, cloud.len());
+        assert!(cloud.is_empty());
+        assert!(cloud.ids().is_empty());
+
+        let cloud = cloud_of(
+            0,
+            &[(3, 3.0, 0.0, 0.0), (1, 1.0, 0.0, 0.0), (2, 2.0, 0.0, 0.0)],
+        );
+        assert_eq!(3, cloud.len());
+        assert!(!cloud.is_empty());
+        assert_eq!(vec![1, 2, 3], cloud.ids());
+    }
+
+    #[test]
+    fn test_cloud_route_from() {
+        for size in [3, 6, 12] {
+            let places = (1..=size)
+                .map(|id| {
+                    let x = (id as f64 * 7.0) % 11.0;
+                    let y = (id as f64 * 3.0) % 5.0;
+                    (id, x, y, 0.0)
+                })
+                .collect::<Vec<_>>();
+            let cloud = cloud_of(0, &places);
+            for solver in Solver::value_variants() {
+                for start in [1, size / 2, size] {
+                    let options = RouteOptions {
+                        solver: *solver,
+                        start: Some(start),
+                        ..RouteOptions::default()
+                    };
+                    let (distance, route) = cloud.solve(&options);
+                    assert_eq!(start, route[0], "{solver:?} on {size} belts");
+                    assert_eq!(
+                        cloud.ids(),
+                        route.iter().cloned().sorted().collect::<Vec<_>>()
+                    );
+                    assert!(approx_eq(distance, cloud.route_distance(&route)));
+                }
+            }
+        }
+
+        let cloud = cloud_of(
+            0,
+            &[(1, 0.0, 0.0, 0.0), (2, 1.0, 0.0, 0.0), (3, 2.0, 0.0, 0.0)],
+        );
+        assert_eq!(Some(2), cloud.find("system i - asteroid belt 2"));
+        assert_eq!(Some(3), cloud.find("3"));
+        assert_eq!(None, cloud.find("4"));
+        let options = RouteOptions {
+            solver: Solver::Size,
+            start: cloud.find("2"),
+            ..RouteOptions::default()
+        };
+        assert_eq!((3.0, vec![2, 1, 3]), cloud.solve(&options));
+    }
+
+    #[test]
+    fn test_cloud_farthest_pair() {
+        assert_eq!(None, Cloud::new().farthest_pair());
+
+        let cloud = cloud_of(0, &[(1, 0.0, 0.0, 0.0)]);
+        assert_eq!(None, cloud.farthest_pair());
+
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 3.0, 4.0, 0.0),
+                (3, -3.0, -4.0, 0.0),
+                (4, 1.0, 1.0, 0.0),
+            ],
+        );
+        assert_eq!(Some((2, 3, 10.0)), cloud.farthest_pair());
+    }
+
+    #[test]
+    fn test_cloud_cheapest_and_most_expensive_edges() {
+        assert_eq!(None, Cloud::new().cheapest_edge());
+        assert_eq!(None, Cloud::new().most_expensive_edge());
+
+        let cloud = cloud_of(0, &[(1, 0.0, 0.0, 0.0)]);
+        assert_eq!(None, cloud.cheapest_edge());
+        assert_eq!(None, cloud.most_expensive_edge());
+
+        let mut cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 3.0, 4.0, 0.0),
+                (3, -3.0, -4.0, 0.0),
+                (4, 1.0, 1.0, 0.0),
+                (5, 1.0, 2.0, 0.0),
+            ],
+        );
+        assert_eq!(Some((4, 5, 1.0)), cloud.cheapest_edge());
+        assert_eq!(Some((2, 3, 10.0)), cloud.most_expensive_edge());
+
+        // The ties go to the smaller ids
+        cloud.add(
+            &6,
+            "System I - Asteroid Belt 6",
+            &Position::new(&0.0, &1.0, &0.0),
+        );
+        assert_eq!(Some((1, 6, 1.0)), cloud.cheapest_edge());
+    }
+
+    #[test]
+    fn test_cloud_farthest_insertion() {
+        // Four tight clusters along an arch
+        let centers = [(0.0, 0.0), (20.0, 60.0), (80.0, 60.0), (100.0, 0.0)];
+        let offsets = [(0.0, 0.0), (3.0, 1.0), (1.0, 4.0), (-2.0, 2.0)];
+        let places = centers
+            .into_iter()
+            .cartesian_product(offsets)
+            .zip(1..)
+            .map(|(((cx, cy), (dx, dy)), id)| (id, cx + dx, cy + dy, 0.0))
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+
+        let (distance, route) = cloud.get_route(&Solver::FarthestInsertion);
+        assert_eq!(
+            cloud.ids(),
+            route.iter().cloned().sorted().collect::<Vec<_>>()
+        );
+        assert!(approx_eq(distance, cloud.route_distance(&route)));
+
+        let (greedy, _) = cloud.get_route(&Solver::NearestNeighbor);
+        assert!(distance <= greedy || approx_eq(distance, greedy));
+    }
+
+    #[test]
+    fn test_saving_percent() {
+        assert_eq!(0.0, saving_percent(0.0, 0.0));
+        assert_eq!(0.0, saving_percent(955.0, 955.0));
+        assert_eq!(25.0, saving_percent(1240.0, 930.0));
+        assert!((saving_percent(1240.0, 955.0) - 22.98).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_thread_pool() {
+        let pool = thread_pool(2).unwrap();
+        assert_eq!(2, pool.current_num_threads());
+        assert_eq!(2, pool.install(rayon::current_num_threads));
+
+        let places = (1..=8)
+            .map(|id| {
+                let x = (id as f64 * 7.0) % 11.0;
+                let y = (id as f64 * 3.0) % 5.0;
+                (id, x, y, 0.0)
+            })
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        let single = thread_pool(1).unwrap();
+        assert_eq!(1, single.current_num_threads());
+        assert_eq!(
+            single.install(|| cloud.get_best_route()),
+            pool.install(|| cloud.get_best_route())
+        );
+    }
+
+    #[test]
+    fn test_solve_clouds() {
+        let clouds: Vec<Cloud> = (0..4)
+            .map(|n| {
+                let places = (1..=7)
+                    .map(|id| {
+                        let x = ((id + n) as f64 * 7.0) % 11.0;
+                        let y = ((id * n) as f64 * 3.0) % 5.0;
+                        (n * 10 + id, x, y, 0.0)
+                    })
+                    .collect::<Vec<_>>();
+                cloud_of(40000000 + n, &places)
+            })
+            .collect();
+        let options = vec![RouteOptions::default(); clouds.len()];
+        let mut fixed = vec![None; clouds.len()];
+        let sequential: Vec<(Route, Route)> = clouds
+            .iter()
+            .map(|cloud| {
+                (
+                    cloud.get_ordinal_route(),
+                    cloud.solve(&RouteOptions::default()),
+                )
+            })
+            .collect();
+        let pool = thread_pool(4).unwrap();
+        let routes = |_, _: &Cloud, _: &RouteOptions, routes| routes;
+        assert_eq!(
+            sequential,
+            solve_clouds(&pool, &clouds, &options, &fixed, routes)
+        );
+
+        let ordinal = clouds[2].get_ordinal_route();
+        fixed[2] = Some(ordinal.1.clone());
+        let solved = solve_clouds(&pool, &clouds, &options, &fixed, routes);
+        assert_eq!((ordinal.clone(), ordinal), solved[2]);
+        assert_eq!(sequential[3], solved[3]);
+    }
+
+    #[tokio::test]
+    async fn test_esi_user_agent() {
+        let server = httpmock::MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.path("/universe/systems/30000001/").header(
+                    "user-agent",
+                    format!(
+                        "best_route/{} (pilot@example.com)",
+                        env!("CARGO_PKG_VERSION")
+                    ),
+                );
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(include_str!("../tests/fixtures/system.json"));
+            })
+            .await;
+
+        let esi = Esi::with_contact(&server.base_url(), "pilot@example.com");
+        let system = System::load(&esi, &30000001).await.unwrap();
+        assert_eq!("Tanoo", system.name);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_esi_gzipped_responses() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let gzip = |body: &str| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let server = httpmock::MockServer::start_async().await;
+        let mut mocks = Vec::new();
+        for (path, body) in [
+            (
+                "/universe/systems/30000001/",
+                include_str!("../tests/fixtures/system.json"),
+            ),
+            (
+                "/universe/asteroid_belts/40000003/",
+                include_str!("../tests/fixtures/asteroid_belt.json"),
+            ),
+            (
+                "/universe/planets/40000002/",
+                r#"{"name": "Tanoo I", "planet_id": 40000002, "position": {"x": 1.0, "y": 2.0, "z": 3.0}, "system_id": 30000001}"#,
+            ),
+        ] {
+            let body = gzip(body);
+            mocks.push(
+                server
+                    .mock_async(|when, then| {
+                        when.path(path).header("accept-encoding", "gzip, deflate");
+                        then.status(200)
+                            .header("content-type", "application/json")
+                            .header("content-encoding", "gzip")
+                            .body(body);
+                    })
+                    .await,
+            );
+        }
+
+        let esi = Esi::new(&server.base_url());
+        let system = System::load(&esi, &30000001).await.unwrap();
+        assert_eq!("Tanoo", system.name);
+        let belt = AsteroidBelt::load(&esi, &40000003).await.unwrap();
+        assert_eq!("Tanoo I - Asteroid Belt 1", belt.name);
+        let planet = PlanetInfo::load(&esi, &40000002).await.unwrap();
+        assert_eq!("Tanoo I", planet.name);
+        for mock in &mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[test]
+    fn test_dump_clouds() {
+        let clouds = [40000002, 40000007]
+            .into_iter()
+            .enumerate()
+            .map(|(n, planet)| {
+                let places = (1..=3)
+                    .map(|id| (id + 10 * n as i32, id as f64 * 1.5e9, -2.5e8, n as f64))
+                    .collect::<Vec<_>>();
+                cloud_of(planet, &places)
+            })
+            .collect::<Vec<Cloud>>();
+
+        let dir = std::env::temp_dir().join(format!("best_route_dump_{}", std::process::id()));
+        let paths = dump_clouds(&dir, &30000001, &clouds).unwrap();
+        assert_eq!(
+            vec![dir.join("30000001_0.json"), dir.join("30000001_1.json")],
+            paths
+        );
+        for (path, cloud) in paths.iter().zip(&clouds) {
+            let restored: Cloud =
+                serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            assert_eq!(cloud, &restored);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_position_bearing() {
+        let origin = Position::new(&10.0, &5.0, &-3.0);
+        let towards = |x: f64, y: f64, z: f64| {
+            let to = Position::new(&(origin.x + x), &(origin.y + y), &(origin.z + z));
+            Position::bearing(&origin, &to)
+        };
+        assert_eq!(0.0, towards(0.0, 0.0, 1.0));
+        assert_eq!(90.0, towards(2.0, 0.0, 0.0));
+        assert_eq!(180.0, towards(0.0, 0.0, -3.0));
+        assert_eq!(270.0, towards(-4.0, 0.0, 0.0));
+        assert!((towards(1.0, 0.0, 1.0) - 45.0).abs() < 1e-9);
+        assert!((towards(-1.0, 0.0, -1.0) - 225.0).abs() < 1e-9);
+        // The vertical part of the hop doesn't change the bearing
+        assert_eq!(90.0, towards(2.0, 100.0, 0.0));
+    }
+
+    #[test]
+    fn test_cloud_route_coordinates() {
+        let places = (1..=3)
+            .map(|id| (id, id as f64, 0.0, -(id as f64)))
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+
+        let coordinates = cloud.route_coordinates(&[3, 1, 42, 2]);
+        assert_eq!(
+            vec![
+                Position::new(&3.0, &0.0, &-3.0),
+                Position::new(&1.0, &0.0, &-1.0),
+                Position::new(&2.0, &0.0, &-2.0),
+            ],
+            coordinates
+        );
+        assert!(cloud.route_coordinates(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_lin_kernighan() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let random_cloud = |seed: u64, size: i32| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let places = (1..=size)
+                .map(|id| {
+                    (
+                        id,
+                        rng.gen_range(0.0..100.0),
+                        rng.gen_range(0.0..10.0),
+                        rng.gen_range(0.0..100.0),
+                    )
+                })
+                .collect::<Vec<_>>();
+            cloud_of(0, &places)
+        };
+
+        for seed in 0..10 {
+            let cloud = random_cloud(seed, 30);
+            let points = cloud.get_ids_sorted_by_name();
+            let (_, mut route) = cloud.lazzy_walker(&points, None);
+            cloud.two_opt(&mut route, 0, None);
+            let two_opt = cloud.route_distance(&route);
+
+            let (distance, route) = cloud.get_route(&Solver::LinKernighan);
+            assert_eq!(30, route.iter().unique().count());
+            assert!(!is_shorter(two_opt, distance), "seed {seed}");
+        }
+
+        for seed in 0..10 {
+            let cloud = random_cloud(seed, 8);
+            let (optimal, _) = cloud.get_route(&Solver::Size);
+            let (distance, route) = cloud.get_route(&Solver::LinKernighan);
+            assert!(approx_eq(optimal, distance), "seed {seed}");
+            assert_eq!(Some(true), cloud.route_is_optimal(&route));
+        }
+
+        let options = RouteOptions {
+            solver: Solver::LinKernighan,
+            start: Some(5),
+            ..RouteOptions::default()
+        };
+        let (_, route) = random_cloud(42, 20).solve(&options);
+        assert_eq!(Some(&5), route.first());
+        assert_eq!(20, route.len());
+    }
+
+    #[test]
+    fn test_brute_force_guard() {
+        assert_eq!(1, permutations(1, false));
+        assert_eq!(3, permutations(3, false));
+        assert_eq!(24, permutations(5, true));
+        assert_eq!(u64::MAX / 2, permutations(30, false));
+
+        let places = (1..=14)
+            .map(|id| (id, ((id * id % 17) as f64), 0.0, ((id * 7 % 11) as f64)))
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        // 14 places would take hours to brute force
+        let options = RouteOptions {
+            brute_threshold: 15,
+            ..RouteOptions::default()
+        };
+        assert_eq!(
+            cloud.get_route(&Solver::NearestNeighbor),
+            cloud.solve(&options)
+        );
+
+        let options = RouteOptions {
+            brute_threshold: 15,
+            permutation_limit: 30,
+            ..RouteOptions::default()
+        };
+        let mut five = Cloud::new();
+        for id in 1..=5 {
+            five.add_place(cloud.places[&id].clone());
+        }
+        // 5 places need 60 routes
+        assert_eq!(
+            five.get_route(&Solver::NearestNeighbor),
+            five.solve(&options)
+        );
+        let options = RouteOptions {
+            permutation_limit: 60,
+            ..options
+        };
+        assert_eq!(five.get_route(&Solver::Size), five.solve(&options));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch() {
+        let mut runs = 0;
+        let count = watch(
+            Duration::from_secs(10),
+            tokio::time::sleep(Duration::from_secs(35)),
+            || {
+                runs += 1;
+                async { Ok(()) }
+            },
+            |err| panic!("{err}"),
+        )
+        .await;
+        // At 0, 10, 20 and 30 seconds
+        assert_eq!(4, count);
+        assert_eq!(4, runs);
+
+        // The failed runs don't stop the watch
+        let mut errors = Vec::new();
+        let count = watch(
+            Duration::from_secs(10),
+            tokio::time::sleep(Duration::from_secs(35)),
+            || async { anyhow::bail!("ESI is down") },
+            |err| errors.push(format_error(&err, false)),
+        )
+        .await;
+        assert_eq!(4, count);
+        assert_eq!(vec!["Error: ESI is down"; 4], errors);
+
+        let parsed = Args::try_parse_from(["best_route", "--watch", "0", "Tanoo"]);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn test_cloud_semantically_equals() {
+        let places = [(1, 0.3, 2.0), (2, 5.0, -1.0), (3, 1e6, 7.5)];
+        let cloud = cloud_of(40000002, &places.map(|(id, x, z)| (id, x, 0.0, z)));
+
+        // The same places in the reversed order with the floating point noise
+        let noisy = places
+            .into_iter()
+            .rev()
+            .map(|(id, x, z)| (id, if x == 0.3 { 0.1 + 0.2 } else { x }, 0.0, z))
+            .collect::<Vec<_>>();
+        let mut other = cloud_of(40000002, &noisy);
+        assert_ne!(cloud, other);
+        assert!(cloud.semantically_equals(&other));
+        assert!(other.semantically_equals(&cloud));
+
+        let mut moved = other.clone();
+        moved.add(
+            &3,
+            "System I - Asteroid Belt 3",
+            &Position::new(&1e6, &1.0, &7.5),
+        );
+        assert!(!cloud.semantically_equals(&moved));
+        other.planet_id = 40000007;
+        assert!(!cloud.semantically_equals(&other));
+        assert!(!cloud.semantically_equals(&Cloud::with_planet(&40000002)));
+    }
+
+    #[test]
+    fn test_examples() {
+        let text = examples();
+        for flag in [
+            "--format json",
+            "--planets",
+            "--solver",
+            "--watch",
+            "Tanoo Jita",
+        ] {
+            assert!(text.contains(flag), "{flag}");
+        }
+        assert!(text
+            .starts_with("Route the asteroid belts of a single system:\n    best_route Tanoo\n"));
+
+        // Every example is accepted by the current flags
+        for (example, _) in EXAMPLES {
+            let mut words = vec!["best_route".to_string()];
+            let mut quoted = example.split('"');
+            while let Some(plain) = quoted.next() {
+                words.extend(plain.split_whitespace().map(String::from));
+                words.extend(quoted.next().map(String::from));
+            }
+            let args = Args::try_parse_from(&words).unwrap();
+            assert!(!args.names.is_empty(), "{example}");
+        }
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(0, edit_distance("tanoo", "tanoo"));
+        assert_eq!(1, edit_distance("tano", "tanoo"));
+        assert_eq!(2, edit_distance("jtia", "jita"));
+        assert_eq!(5, edit_distance("", "jita."));
+    }
+
+    #[tokio::test]
+    async fn test_search_systems() {
+        let server = httpmock::MockServer::start_async().await;
+        let search = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET)
+                    .path("/search/")
+                    .query_param("categories", "solar_system")
+                    .query_param("search", "tan")
+                    .query_param("strict", "false");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(r#"{"solar_system": [30000001, 30002737, 30045316]}"#);
+            })
+            .await;
+        let names = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/universe/names/")
+                    .json_body(serde_json::json!([30000001, 30002737, 30045316]));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(
+                        r#"[{"category": "solar_system", "id": 30000001, "name": "Tanoo"},
+                            {"category": "solar_system", "id": 30002737, "name": "Konstan"},
+                            {"category": "solar_system", "id": 30045316, "name": "Tannakan"}]"#,
+                    );
+            })
+            .await;
+
+        let esi = Esi::new(&server.base_url());
+        let candidates = Search::systems(&esi, "tan").await.unwrap();
+        let names_found = candidates
+            .iter()
+            .map(|obj| obj.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(vec!["Tanoo", "Tannakan", "Konstan"], names_found);
+        assert_eq!(
+            Some(30000001),
+            pick_candidate("tan", &candidates, None).unwrap()
+        );
+        let mut answer = "2\n".as_bytes();
+        let picked = pick_candidate("tan", &candidates, Some(&mut answer)).unwrap();
+        assert_eq!(Some(30045316), picked);
+        let mut answer = "7\n".as_bytes();
+        let picked = pick_candidate("tan", &candidates, Some(&mut answer)).unwrap();
+        assert_eq!(None, picked);
+        assert_eq!(None, pick_candidate("tan", &[], None).unwrap());
+        search.assert_async().await;
+        names.assert_async().await;
+    }
+
+    #[test]
+    fn test_route_lines_cumulative_distance() {
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 40e6, 0.0, 0.0),
+                (3, 50e6, 0.0, 0.0),
+                (4, 120e6, 0.0, 0.0),
+            ],
+        );
+        let args = Args::parse_from(["best_route", "--number-style", "plain", "Tanoo"]);
+        let lines = route_lines(&cloud, cloud.get_route(&Solver::Size), &args);
+        assert_eq!(5, lines.len());
+        assert_eq!(" 1 Warp to `System I - Asteroid Belt 1`", lines[0]);
+        assert_eq!(
+            " 3 Warp to `System I - Asteroid Belt 3` - 10 Mm (cum 50 Mm)",
+            lines[2]
+        );
+        assert_eq!("The length of the route: 120 Mm", lines[4]);
+
+        let covered = lines[1..4]
+            .iter()
+            .map(|line| {
+                let (_, cum) = line.split_once("(cum ").unwrap();
+                cum.trim_end_matches(" Mm)").parse::<f64>().unwrap()
+            })
+            .collect::<Vec<f64>>();
+        assert!(covered.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(Some(&120.0), covered.last());
+    }
+
+    #[test]
+    fn test_cloud_optimize_in_place() {
+        let places = (1..=12)
+            .map(|id| {
+                let angle = id as f64 * std::f64::consts::PI / 6.0;
+                (id, (100.0 * angle.cos()), 0.0, (100.0 * angle.sin()))
+            })
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        // Jumping across the circle
+        let mut route = vec![1, 7, 2, 8, 3, 9, 4, 10, 5, 11, 6, 12];
+        let before = cloud.route_distance(&route);
+
+        let after = cloud.optimize_in_place(&mut route);
+        assert!(is_shorter(after, before));
+        assert!(approx_eq(after, cloud.route_distance(&route)));
+        assert_eq!(
+            cloud.ids(),
+            route.iter().cloned().sorted().collect::<Vec<i32>>()
+        );
+        // Walking along the circle is the optimum
+        let side = 200.0 * (std::f64::consts::PI / 12.0).sin();
+        assert!(approx_eq(11.0 * side, after));
+
+        // The optimum can't be improved
+        assert!(approx_eq(after, cloud.optimize_in_place(&mut route)));
+    }
+
+    #[test]
+    fn test_name_template() {
+        let standard = NameTemplate::default();
+        assert_eq!(Some((3, 2)), standard.parse("Tanoo III - Asteroid Belt 2"));
+        assert_eq!(
+            Some((6, 11)),
+            standard.parse("Old Man Star VI - Asteroid Belt 11")
+        );
+        assert_eq!(None, standard.parse("Tanoo III - Ice Field 2"));
+        assert_eq!(None, standard.parse("Tanoo - Asteroid Belt 2"));
+
+        let custom = "Belt #{belt} of {system}/{cloud}"
+            .parse::<NameTemplate>()
+            .unwrap();
+        assert_eq!(Some((4, 7)), custom.parse("Belt #7 of Test System/IV"));
+        assert_eq!(Some((12, 1)), custom.parse("Belt #1 of Sandbox/12"));
+        assert_eq!(None, custom.parse("Belt #x of Sandbox/12"));
+
+        let place = Place::parsed(&1, "Belt #7 of Test/IV", &Position::default(), &custom);
+        assert_eq!((4, 7), (place.cloud_number, place.belt_number));
+        let place = Place::parsed(&2, "Unknown", &Position::default(), &custom);
+        assert_eq!((0, 0), (place.cloud_number, place.belt_number));
+        assert!(Place::checked(&2, "Unknown", &Position::default(), &custom).is_err());
+
+        // An absurd numeral doesn't number the cloud
+        let err = Place::checked(
+            &3,
+            "Tanoo MMMCMXCIX - Asteroid Belt 1",
+            &Position::default(),
+            &standard,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "`Tanoo MMMCMXCIX - Asteroid Belt 1` has the cloud number 3999 out of 1..=50",
+            err.to_string()
+        );
+        assert!(Place::checked(&4, "Belt #1 of Sandbox/0", &Position::default(), &custom).is_err());
+        let place = Place::parsed(&5, "Belt #1 of Sandbox/999", &Position::default(), &custom);
+        assert_eq!((0, 0), (place.cloud_number, place.belt_number));
+        assert!(Place::checked(
+            &6,
+            "Tanoo L - Asteroid Belt 1",
+            &Position::default(),
+            &standard
+        )
+        .is_ok());
+
+        // The standard places fail rather than panic on the names they can't number
+        let place = Place::new(&7, "Tanoo IV - Asteroid Belt 3", &Position::default()).unwrap();
+        assert_eq!((4, 3), (place.cloud_number, place.belt_number));
+        assert_eq!(
+            "`Tanoo MMMCMXCIX - Asteroid Belt 1` has the cloud number 3999 out of 1..=50",
+            Place::new(
+                &8,
+                "Tanoo MMMCMXCIX - Asteroid Belt 1",
+                &Position::default()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "`Tanoo - Asteroid Belt 1` doesn't match the name template",
+            Place::new(&9, "Tanoo - Asteroid Belt 1", &Position::default())
+                .unwrap_err()
+                .to_string()
+        );
+
+        assert!("{cloud}{belt}".parse::<NameTemplate>().is_err());
+        assert!("Belt {belt".parse::<NameTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_cloud_route_distance() {
+        let cloud = cloud_of(
+            0,
+            &[(1, 0.0, 0.0, 0.0), (2, 3.0, 4.0, 0.0), (3, 3.0, 0.0, 0.0)],
+        );
+        assert_eq!(0.0, cloud.route_distance(&[]));
+        assert_eq!(0.0, cloud.route_distance(&[2]));
+        assert_eq!(9.0, cloud.route_distance(&[1, 2, 3]));
+        assert_eq!(7.0, cloud.route_distance(&[2, 3, 1]));
+        assert_eq!(f64::INFINITY, cloud.route_distance(&[1, 42, 3]));
+        assert!(!is_shorter(cloud.route_distance(&[42]), 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let server = httpmock::MockServer::start_async().await;
+        let mut mocks = Vec::new();
+        for (id, belts) in [(30000001, "[40000003, 40000004]"), (30000002, "[40000013]")] {
+            let body = format!(
+                r#"{{"constellation_id": 20000001, "name": "System {id}", "security_status": 0.8,
+                    "planets": [{{"planet_id": {}, "asteroid_belts": {belts}}}, {{"planet_id": 1}}],
+                    "system_id": {id}}}"#,
+                id + 10000000
+            );
+            mocks.push(
+                server
+                    .mock_async(|when, then| {
+                        when.path(format!("/universe/systems/{id}/"));
+                        then.status(200)
+                            .header("content-type", "application/json")
+                            .body(body);
+                    })
+                    .await,
+            );
+        }
+        for id in [40000003, 40000004, 40000013] {
+            mocks.push(
+                server
+                    .mock_async(|when, then| {
+                        when.path(format!("/universe/asteroid_belts/{id}/"));
+                        then.status(200)
+                            .header("content-type", "application/json")
+                            .body(include_str!("../tests/fixtures/asteroid_belt.json"));
+                    })
+                    .await,
+            );
+        }
+
+        let esi = Esi::new(&server.base_url());
+        let prefetch = Prefetch::load(&esi, &[30000001, 30000002, 30000001], None)
+            .await
+            .unwrap();
+        let systems = prefetch
+            .systems
+            .keys()
+            .cloned()
+            .sorted()
+            .collect::<Vec<i32>>();
+        assert_eq!(vec![30000001, 30000002], systems);
+        let belts = prefetch
+            .belts
+            .keys()
+            .cloned()
+            .sorted()
+            .collect::<Vec<i32>>();
+        assert_eq!(vec![40000003, 40000004, 40000013], belts);
+        for mock in &mocks {
+            mock.assert_hits_async(1).await;
+        }
+
+        let failed = Prefetch::load(&esi, &[30000003], None).await.unwrap_err();
+        assert_eq!("Failed to load the system 30000003", failed.to_string());
+    }
+
+    #[test]
+    fn test_cloud_route_with_return_total() {
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 30e6, 0.0, 0.0),
+                (3, 30e6, 0.0, 40e6),
+            ],
+        );
+        assert_eq!((70e6, 50e6), cloud.route_with_return_total(&[1, 2, 3]));
+        assert_eq!((80e6, 40e6), cloud.route_with_return_total(&[2, 1, 3]));
+        assert_eq!((0.0, 0.0), cloud.route_with_return_total(&[1]));
+        assert_eq!((0.0, 0.0), cloud.route_with_return_total(&[]));
+
+        let args = Args::parse_from(["best_route", "--round-trip", "Tanoo"]);
+        let lines = route_lines(&cloud, (70e6, vec![1, 2, 3]), &args);
+        assert_eq!(
+            Some(&"Route 70 Mm, return +50 Mm, round trip 120 Mm".to_string()),
+            lines.last()
+        );
+    }
+
+    #[test]
+    fn test_max_runtime() {
+        let places = (1..=14)
+            .map(|id| (id, ((id * id % 17) as f64), 0.0, ((id * 7 % 11) as f64)))
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        // 14 places would take hours to brute force
+        let options = RouteOptions {
+            brute_threshold: 15,
+            permutation_limit: u64::MAX,
+            deadline: Some(std::time::Instant::now() + Duration::from_millis(50)),
+            ..RouteOptions::default()
+        };
+        let begin = std::time::Instant::now();
+        let (distance, route) = cloud.solve(&options);
+        assert!(begin.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            cloud.ids(),
+            route.iter().cloned().sorted().collect::<Vec<i32>>()
+        );
+        assert!(approx_eq(distance, cloud.route_distance(&route)));
+        let (greedy, _) = cloud.get_route(&Solver::NearestNeighbor);
+        assert!(!is_shorter(greedy, distance));
+
+        let options = RouteOptions {
+            solver: Solver::LinKernighan,
+            deadline: Some(std::time::Instant::now()),
+            ..RouteOptions::default()
+        };
+        let (distance, route) = cloud.solve(&options);
+        assert_eq!(14, route.iter().unique().count());
+        assert!(!is_shorter(greedy, distance));
+
+        let parse = |value| {
+            Args::try_parse_from(["best_route", &format!("--max-runtime={value}"), "Tanoo"])
+        };
+        assert_eq!(Some(0.5), parse("0.5").unwrap().max_runtime);
+        for value in ["0", "-1", "NaN", "inf", "soon"] {
+            let err = parse(value).unwrap_err().to_string();
+            assert!(
+                err.contains(&format!("`{value}` is not a positive number")),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_route_lines_show_ids() {
+        let mut cloud = Cloud::new();
+        for (id, x) in [(40000003, 0.0), (40000008, 2e6)] {
+            cloud.add(
+                &id,
+                &format!("System I - Asteroid Belt {}", id % 10),
+                &Position::new(&x, &0.0, &0.0),
+            );
+        }
+        let route = cloud.get_ordinal_route();
+        let args = Args::parse_from(["best_route", "--show-ids", "Tanoo"]);
+        let lines = route_lines(&cloud, route.clone(), &args);
+        assert_eq!(
+            " 1 Warp to `System I - Asteroid Belt 3` (id 40000003)",
+            lines[0]
+        );
+        assert_eq!(
+            " 2 Warp to `System I - Asteroid Belt 8` (id 40000008) - 2 Mm (cum 2 Mm)",
+            lines[1]
+        );
+
+        let args = Args::parse_from(["best_route", "Tanoo"]);
+        let lines = route_lines(&cloud, route, &args);
+        assert!(lines.iter().all(|line| !line.contains("(id ")));
+        let single = route_lines(
+            &cloud,
+            (0.0, vec![40000008]),
+            &Args::parse_from(["best_route", "--show-ids", "Tanoo"]),
+        );
+        assert_eq!(
+            vec![" 1 Warp to `System I - Asteroid Belt 8` (id 40000008)"],
+            single
+        );
+    }
+
+    #[test]
+    fn test_cloud_nearest_places_to() {
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 10.0, 0.0, 0.0),
+                (3, 25.0, 0.0, 0.0),
+                (4, 40.0, 0.0, 0.0),
+            ],
+        );
+        let position = Position::new(&22.0, &4.0, &0.0);
+        assert_eq!(
+            vec![(3, 5.0), (2, (144.0f64 + 16.0).sqrt())],
+            cloud.nearest_places_to(&position, 2)
+        );
+        assert_eq!(4, cloud.nearest_places_to(&position, 10).len());
+        assert!(cloud.nearest_places_to(&position, 0).is_empty());
+        assert!(Cloud::new().nearest_places_to(&position, 3).is_empty());
+
+        assert_eq!(Some(4), cloud.find("38.5, 1e3, -2"));
+        assert_eq!(Some(1), cloud.find("-5,0,0"));
+        assert_eq!(None, cloud.find("1,2"));
+    }
+
+    #[test]
+    fn test_diff_reports() {
+        let report = |planet_id, route: &[(i32, f64)]| {
+            let places = route
+                .iter()
+                .map(|(id, x)| (*id, *x, 0.0, 0.0))
+                .collect::<Vec<_>>();
+            let cloud = cloud_of(planet_id, &places);
+            let ids = route.iter().map(|(id, _)| *id).collect::<Vec<i32>>();
+            let shortest = (cloud.route_distance(&ids), ids);
+            RouteReport::new(&cloud, &shortest, &shortest)
+        };
+        let system = |clouds| SystemReport {
+            system_id: 30000001,
+            name: "Tanoo".to_string(),
+            security_status: 0.8,
+            clouds,
+        };
+        let old = [system(vec![
+            report(40000002, &[(1, 0.0), (2, 10e6), (3, 20e6)]),
+            report(40000005, &[(5, 0.0)]),
+        ])];
+        let new = [system(vec![
+            report(40000002, &[(2, 10e6), (1, 0.0), (3, 20e6), (4, 60e6)]),
+            report(40000006, &[(6, 0.0)]),
+        ])];
+
+        assert_eq!(
+            vec![
+                "Tanoo planet 40000005: the cloud is removed",
+                "Tanoo planet 40000002: + `System I - Asteroid Belt 4`",
+                "Tanoo planet 40000002: the order is changed",
+                "Tanoo planet 40000002: 20 Mm -> 70 Mm (+50 Mm)",
+                "Tanoo planet 40000006: the cloud is added",
+            ],
+            diff_reports(&old, &new, &NumberStyle::Plain, 0)
+        );
+        assert_eq!(
+            vec![
+                "Tanoo planet 40000006: the cloud is removed",
+                "Tanoo planet 40000002: - `System I - Asteroid Belt 4`",
+                "Tanoo planet 40000002: the order is changed",
+                "Tanoo planet 40000002: 70 Mm -> 20 Mm (-50 Mm)",
+                "Tanoo planet 40000005: the cloud is added",
+            ],
+            diff_reports(&new, &old, &NumberStyle::Plain, 0)
+        );
+        assert!(diff_reports(&old, &old, &NumberStyle::Plain, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_system_validation() {
+        let server = httpmock::MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.path("/universe/systems/30000001/");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(r#"{"name": "Tanoo", "security_status": 0.8, "system_id": 30000001}"#);
+            })
+            .await;
+        let esi = Esi::new(&server.base_url());
+        let err = System::load(&esi, &30000001).await.unwrap_err();
+        assert_eq!(
+            "The system 30000001 has no constellation_id",
+            err.to_string()
+        );
+
+        let system: System = serde_json::from_str(
+            r#"{"constellation_id": 0, "name": "Tanoo", "security_status": 0.8, "system_id": 30000001}"#,
+        )
+        .unwrap();
+        let err = system.validate(&30000001).unwrap_err();
+        assert_eq!(
+            "The system 30000001 has no constellation_id",
+            err.to_string()
+        );
+        let err = System::default().validate(&30000001).unwrap_err();
+        assert_eq!("The system 30000001 has no system_id", err.to_string());
+        let system: System =
+            serde_json::from_str(r#"{"name": "Tanoo", "security_status": 0.8}"#).unwrap();
+        let err = system.validate(&30000001).unwrap_err();
+        assert_eq!("The system 30000001 has no system_id", err.to_string());
+
+        let system: System =
+            serde_json::from_str(include_str!("../tests/fixtures/system.json")).unwrap();
+        assert!(system.validate(&30000001).is_ok());
+        let err = system.validate(&30000002).unwrap_err();
+        assert_eq!(
+            "The system 30000002 came as the system 30000001",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_color() {
+        assert_eq!(ColorChoice::Never, ColorChoice::Always.resolve(true));
+        assert_eq!(ColorChoice::Always, ColorChoice::Always.resolve(false));
+        assert_eq!(ColorChoice::Never, ColorChoice::Never.resolve(false));
+
+        let cloud = cloud_of(
+            0,
+            &[(1, 0.0, 0.0, 0.0), (2, 10e6, 0.0, 0.0), (3, 40e6, 0.0, 0.0)],
+        );
+        let route = cloud.get_ordinal_route();
+        let lines = |color| {
+            let args = Args::parse_from(["best_route", "--color", color, "Tanoo"]);
+            route_lines(&cloud, route.clone(), &args).join("\n")
+        };
+        let never = lines("never");
+        assert!(!never.contains('\x1b'));
+        assert!(never.contains("Warp to `System I - Asteroid Belt 3` - 30 Mm (cum 40 Mm)"));
+        assert!(lines("always").contains("\x1b["));
+        // Unresolved auto is plain
+        assert_eq!(never, lines("auto"));
+    }
+
+    #[test]
+    fn test_cloud_add_many() {
+        let items = (1..=6)
+            .map(|id| {
+                (
+                    id,
+                    format!("System II - Asteroid Belt {id}"),
+                    Position::new(&(id as f64 * 1.5), &-(id as f64), &((id * id) as f64)),
+                )
+            })
+            .collect::<Vec<(i32, String, Position)>>();
+
+        let mut sequential = Cloud::with_planet(&40000002);
+        for (id, name, position) in &items {
+            sequential.add(id, name, position);
+        }
+        let mut bulk = Cloud::with_planet(&40000002);
+        bulk.add_many(&items);
+        assert_eq!(sequential, bulk);
+        assert_eq!(
+            Some(2),
+            bulk.get_name(&2).map(|_| bulk.places[&2].cloud_number)
+        );
+
+        // More places join the ones already in the cloud
+        let mut more = Cloud::with_planet(&40000002);
+        more.add_many(&items[..2]);
+        more.add_many(&items[2..]);
+        assert_eq!(sequential, more);
+
+        let mut single = Cloud::new();
+        single.add_many(&items[..1]);
+        assert_eq!(1, single.len());
+        assert_eq!(None, single.distance_between(&1, &1));
+    }
+
+    #[test]
+    fn test_system_lines() {
+        let first = cloud_of(
+            40000002,
+            &[(1, 0.0, 0.0, 0.0), (2, 20e6, 0.0, 0.0), (3, 10e6, 0.0, 0.0)],
+        );
+        let mut second = Cloud::with_planet(&40000007);
+        second.add(
+            &4,
+            "Tanoo III - Asteroid Belt 1",
+            &Position::new(&0.0, &0.0, &0.0),
+        );
+        let args = Args::parse_from(["best_route", "Tanoo"]);
+        let lines = |clouds: &[Cloud]| {
+            let routes = clouds
+                .iter()
+                .map(|cloud| (cloud.get_ordinal_route(), cloud.get_route(&Solver::Size)))
+                .collect::<Vec<_>>();
+            let reports = clouds
+                .iter()
+                .zip(&routes)
+                .map(|(cloud, (ordinal, shortest))| RouteReport::new(cloud, shortest, ordinal))
+                .collect::<Vec<_>>();
+            system_lines(clouds, &routes, &reports, &args)
+        };
+
+        assert_eq!(
+            vec![
+                " 1 Warp to `System I - Asteroid Belt 1`",
+                " 2 Warp to `System I - Asteroid Belt 3` - 10 Mm (cum 10 Mm)",
+                " 3 Warp to `System I - Asteroid Belt 2` - 10 Mm (cum 20 Mm)",
+                "The length of the route: 20 Mm",
+                "Saved 33% vs ordinal (30 Mm → 20 Mm)",
+            ],
+            lines(std::slice::from_ref(&first))
+        );
+
+        let full = lines(&[first, second]);
+        assert_eq!(13, full.len());
+        assert_eq!("\n\t-=[Ordinal route]=-", full[0]);
+        assert_eq!(
+            " 3 Warp to `System I - Asteroid Belt 3` - 10 Mm (cum 30 Mm)",
+            full[3]
+        );
+        assert_eq!("\n\t-=[Shortest route]=-", full[6]);
+        assert_eq!(" 1 Warp to `Tanoo III - Asteroid Belt 1`", full[12]);
+    }
+
+    #[test]
+    fn test_belt_lines() {
+        let mut cloud = Cloud::new();
+        for (id, name, x) in [
+            (40000012, "Tanoo I - Asteroid Belt 1", 0.0),
+            (40000003, "Tanoo I - Asteroid Belt 2", 20e6),
+            (40000025, "Tanoo I - Asteroid Belt 10", 10e6),
+        ] {
+            cloud.add(&id, name, &Position::new(&x, &0.0, &0.0));
+        }
+        let route = vec![40000012, 40000025, 40000003];
+        assert_eq!(
+            vec![
+                "Belts:",
+                "\t40000012 - Tanoo I - Asteroid Belt 1",
+                "\t40000003 - Tanoo I - Asteroid Belt 2",
+                "\t40000025 - Tanoo I - Asteroid Belt 10",
+            ],
+            belt_lines(&cloud, &BeltList::Name, &route)
+        );
+        assert_eq!(
+            vec![
+                "Belts:",
+                "\t40000003 - Tanoo I - Asteroid Belt 2",
+                "\t40000012 - Tanoo I - Asteroid Belt 1",
+                "\t40000025 - Tanoo I - Asteroid Belt 10",
+            ],
+            belt_lines(&cloud, &BeltList::Id, &route)
+        );
+        assert_eq!(
+            vec![
+                "Belts:",
+                "\t40000012 - Tanoo I - Asteroid Belt 1",
+                "\t40000025 - Tanoo I - Asteroid Belt 10",
+                "\t40000003 - Tanoo I - Asteroid Belt 2",
+            ],
+            belt_lines(&cloud, &BeltList::Route, &route)
+        );
+    }
+
+    #[test]
+    fn test_next_permutation() {
+        let mut items = vec![0, 1, 2];
+        let mut all = vec![items.clone()];
+        while next_permutation(&mut items) {
+            all.push(items.clone());
+        }
+        let expected = [0, 1, 2].into_iter().permutations(3).collect::<Vec<_>>();
+        assert_eq!(expected, all);
+        assert_eq!(vec![2, 1, 0], items);
+        assert!(!next_permutation(&mut []));
+        assert!(!next_permutation(&mut [3]));
+    }
+
+    #[test]
+    fn test_strict_anomalies() {
+        let mut cloud = Cloud::new();
+        cloud.add_many(&[
+            (
+                1,
+                "Tanoo I - Asteroid Belt 1".to_string(),
+                Position::new(&1.0, &0.0, &0.0),
+            ),
+            (
+                2,
+                "Tanoo I - Asteroid Belt 2".to_string(),
+                Position::new(&2.0, &0.0, &0.0),
+            ),
+        ]);
+        assert!(cloud.check_places(&[]).is_ok());
+
+        let duplicate = Place::new(
+            &2,
+            "Tanoo I - Asteroid Belt 3",
+            &Position::new(&3.0, &0.0, &0.0),
+        )
+        .unwrap();
+        let err = cloud
+            .check_places(std::slice::from_ref(&duplicate))
+            .unwrap_err();
+        assert!(err.to_string().contains("The old value for 2 was replaced"));
+        let collision = Place::new(
+            &3,
+            "Tanoo I - Asteroid Belt 3",
+            &Position::new(&1.0, &0.0, &0.0),
+        )
+        .unwrap();
+        let err = cloud.check_places(&[collision]).unwrap_err();
+        assert_eq!("1 and 3 are at the same position", err.to_string());
+
+        // Without the strictness the duplicate only replaces the old place
+        cloud.add_places(vec![duplicate]);
+        assert_eq!(2, cloud.len());
+        assert_eq!("Tanoo I - Asteroid Belt 3", cloud.places[&2].name);
+        assert!(approx_eq(2.0, cloud.distance_between(&1, &2).unwrap()));
+
+        assert!(Args::parse_from(["best_route", "--strict", "Tanoo"]).strict);
+        assert!(!Args::parse_from(["best_route", "Tanoo"]).strict);
+    }
+
+    #[test]
+    fn test_cloud_subcloud() {
+        let cloud = cloud_of(
+            40000002,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 3.0, 0.0, 4.0),
+                (3, -2.0, 0.0, 7.0),
+                (4, 9.0, 0.0, -1.0),
+            ],
+        );
+
+        let subcloud = cloud.subcloud(&[4, 2, 1, 2, 99]);
+        assert_eq!(40000002, subcloud.planet_id);
+        assert_eq!(vec![1, 2, 4], subcloud.get_ids_sorted_by_name());
+        for (a, b) in [1, 2, 4].into_iter().tuple_combinations() {
+            assert_eq!(
+                cloud.distance_between(&a, &b),
+                subcloud.distance_between(&a, &b)
+            );
+            assert_eq!(
+                cloud.distance_between(&b, &a),
+                subcloud.distance_between(&b, &a)
+            );
+        }
+        assert_eq!(None, subcloud.distance_between(&1, &3));
+
+        let mut rebuilt = Cloud::with_planet(&40000002);
+        rebuilt.add_places([1, 2, 4].map(|id| cloud.places[&id].clone()).to_vec());
+        assert_eq!(rebuilt, subcloud);
+
+        assert!(cloud.subcloud(&[]).is_empty());
+        assert!(cloud.subcloud(&[99]).is_empty());
+    }
+
+    #[test]
+    fn test_end_at_station() {
+        assert_eq!(
+            Endpoint::Station(60003760),
+            "60003760".parse::<Endpoint>().unwrap()
+        );
+        assert_eq!(
+            Endpoint::At(Position::new(&1.0, &-2.5, &3e9)),
+            "1, -2.5, 3e9".parse::<Endpoint>().unwrap()
+        );
+        assert!("Jita IV - Moon 4".parse::<Endpoint>().is_err());
+
+        let cloud = cloud_of(
+            40000002,
+            &[(1, 0.0, 0.0, 0.0), (2, 20e6, 0.0, 0.0), (3, 10e6, 0.0, 0.0)],
+        );
+        let station = Position::new(&20e6, &0.0, &85e6);
+        assert!(approx_eq(85e6, cloud.station_leg(&[1, 3, 2], &station)));
+        assert_eq!(0.0, cloud.station_leg(&[], &station));
+
+        let args = Args::parse_from(["best_route", "--end-at-station", "20e6,0,85e6", "Tanoo"]);
+        assert_eq!(
+            vec![
+                " 1 Warp to `System I - Asteroid Belt 1`",
+                " 2 Warp to `System I - Asteroid Belt 3` - 10 Mm (cum 10 Mm)",
+                " 3 Warp to `System I - Asteroid Belt 2` - 10 Mm (cum 20 Mm)",
+                "Return to station - 85 Mm",
+                "The length of the route: 105 Mm",
+            ],
+            route_lines(&cloud, (20e6, vec![1, 3, 2]), &args)
+        );
+    }
+
+    #[test]
+    fn test_minimize_objective() {
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 1e6, 0.0, 1e6),
+                (2, 16e6, 0.0, 16e6),
+                (3, 4e6, 0.0, 36e6),
+                (4, 81e6, 0.0, 16e6),
+            ],
+        );
+        let warp = WarpModel::default();
+        let undirected = |mut route: Vec<i32>| {
+            if route.first() > route.last() {
+                route.reverse();
+            }
+            route
+        };
+        let solve = |objective| {
+            cloud.solve(&RouteOptions {
+                objective,
+                ..RouteOptions::default()
+            })
+        };
+
+        let (shortest, by_distance) = solve(Objective::Distance);
+        assert_eq!(vec![3, 1, 2, 4], undirected(by_distance.clone()));
+        let (distance, by_time) = solve(Objective::Time);
+        assert_eq!(vec![1, 2, 3, 4], undirected(by_time.clone()));
+        // The faster route is longer and the reported distance is still the length
+        assert!(approx_eq(distance, cloud.route_distance(&by_time)));
+        assert!(is_shorter(shortest, distance));
+        let time = |route: &[i32]| {
+            warp.route_time(
+                &route
+                    .iter()
+                    .tuple_windows()
+                    .map(|(a, b)| cloud.distance_between(a, b).unwrap())
+                    .collect::<Vec<f64>>(),
+            )
+        };
+        assert!(time(&by_time) < time(&by_distance));
+
+        // No belt is within the default coverage of another
+        let (distance, by_hops) = solve(Objective::Hops);
+        assert_eq!(by_distance, by_hops);
+        assert!(approx_eq(shortest, distance));
+        // The belt 2 covers the belts 1 and 3, the belt 4 is left
+        let (distance, by_hops) = cloud.solve(&RouteOptions {
+            objective: Objective::Hops,
+            coverage: 24e6,
+            ..RouteOptions::default()
+        });
+        assert_eq!(vec![2, 4], undirected(by_hops.clone()));
+        assert!(approx_eq(distance, cloud.route_distance(&by_hops)));
+        // The start is always visited, the belt 2 covers the belt 3 alone then
+        let (_, by_hops) = cloud.solve(&RouteOptions {
+            objective: Objective::Hops,
+            coverage: 24e6,
+            start: Some(1),
+            ..RouteOptions::default()
+        });
+        assert_eq!(vec![1, 2, 4], by_hops);
+
+        let args = Args::parse_from(["best_route", "--minimize", "time", "Tanoo"]);
+        assert_eq!(Objective::Time, args.minimize);
+        assert_eq!(COVERAGE_RADIUS, args.coverage);
+    }
+
+    #[test]
+    fn test_slow_cloud() {
+        let places = (1..=5)
+            .map(|id| (id, id as f64, 0.0, 0.0))
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        assert!(cloud.is_slow(4));
+        assert!(!cloud.is_slow(5));
+        assert!(!cloud.is_slow(SLOW_LIMIT));
+
+        // Routing a slow cloud only warns
+        let options = RouteOptions {
+            slow_threshold: 4,
+            ..RouteOptions::default()
+        };
+        assert_eq!(cloud.get_best_route(), cloud.solve(&options));
+
+        let route = cloud.get_best_route();
+        let mut report = RouteReport::new(&cloud, &route, &cloud.get_ordinal_route());
+        assert!(!serde_json::to_string(&report).unwrap().contains("slow"));
+        report.slow = cloud.is_slow(4);
+        assert!(serde_json::to_string(&report)
+            .unwrap()
+            .contains(r#""slow":true"#));
+    }
+
+    #[test]
+    fn test_insert_best_position() {
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 10.0, 0.0, 0.0),
+                (3, 20.0, 0.0, 0.0),
+                (4, 11.0, 0.0, 1.0),
+            ],
+        );
+
+        let mut route = vec![1, 2, 3];
+        let length = cloud.insert_best_position(&mut route, 4);
+        assert_eq!(vec![1, 2, 4, 3], route);
+        assert!(approx_eq(cloud.route_distance(&route), length));
+        // No other position is shorter
+        for position in 0..=3 {
+            let mut other = vec![1, 2, 3];
+            other.insert(position, 4);
+            assert!(!is_shorter(cloud.route_distance(&other), length));
+        }
+
+        // The ends are the best for the places beyond them
+        let mut route = vec![2, 3];
+        cloud.insert_best_position(&mut route, 1);
+        assert_eq!(vec![1, 2, 3], route);
+
+        let mut route = vec![];
+        assert_eq!(0.0, cloud.insert_best_position(&mut route, 2));
+        assert_eq!(vec![2], route);
+        assert_eq!(0.0, cloud.insert_best_position(&mut route, 99));
+        assert_eq!(vec![2], route);
+    }
+
+    #[test]
+    fn test_audit_system() {
+        let system: System =
+            serde_json::from_str(include_str!("../tests/fixtures/system.json")).unwrap();
+        let belt = |name: &str, x: f64, system_id: i32| AsteroidBelt {
+            name: name.to_string(),
+            position: Position::new(&x, &0.0, &0.0),
+            system_id,
+        };
+        let template = NameTemplate::default();
+
+        let loaded = |belts: Vec<(i32, AsteroidBelt)>| {
+            belts
+                .into_iter()
+                .map(|(id, belt)| (id, Ok(belt)))
+                .collect::<Vec<(i32, anyhow::Result<AsteroidBelt>)>>()
+        };
+
+        let clean = loaded(vec![
+            (40000003, belt("Tanoo I - Asteroid Belt 1", 0.0, 30000001)),
+            (40000008, belt("Tanoo III - Asteroid Belt 1", 1e6, 30000001)),
+        ]);
+        assert!(audit_system(&30000001, &system, &clean, &template).is_empty());
+
+        let mut broken = loaded(vec![
+            (40000003, belt("Tanoo I - Asteroid Belt 1", 0.0, 30000001)),
+            (40000008, belt("Tanoo III - Asteroid Belt 1", 0.0, 30000001)),
+            (40000009, belt("Tanoo III - Ice Field 2", 1e6, 30000002)),
+            (
+                40000010,
+                belt("Tanoo IV - Asteroid Belt 1", f64::MAX, 30000001),
+            ),
+        ]);
+        broken.push((40000011, Err(anyhow::anyhow!("404 Not Found"))));
+        let violations = audit_system(&30000002, &system, &broken, &template);
+        assert_eq!(
+            vec![
+                "The system 30000002 came as the system 30000001",
+                "Failed to load the asteroid belt 40000011: 404 Not Found",
+                "The belt 40000009 belongs to the system 30000002",
+                "The belt 40000009 `Tanoo III - Ice Field 2` doesn't match the name template",
+                "The belts 40000003 and 40000008 are at the same position",
+                "The distance between 40000003 and 40000010 is inf",
+                "The distance between 40000008 and 40000010 is inf",
+                "The distance between 40000009 and 40000010 is inf",
+            ],
+            violations
+        );
+    }
+
+    #[test]
+    fn test_distance_stats() {
+        assert_eq!(DistanceStats::default(), Cloud::new().distance_stats());
+        // The pairs are 1, 3, 4, 6, 9 and 10 Mm apart
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 1e6, 0.0, 0.0),
+                (3, 4e6, 0.0, 0.0),
+                (4, 10e6, 0.0, 0.0),
+            ],
+        );
+        let stats = cloud.distance_stats();
+        assert!(approx_eq(1e6, stats.min));
+        assert!(approx_eq(10e6, stats.max));
+        assert!(approx_eq(5.5e6, stats.mean));
+        assert!(approx_eq(5e6, stats.median));
+        assert!(approx_eq(10e6, stats.diameter));
+
+        let route = cloud.get_best_route();
+        let report = RouteReport::new(&cloud, &route, &cloud.get_ordinal_route());
+        let clouds = [cloud];
+        let routes = [(clouds[0].get_ordinal_route(), route)];
+        let args = Args::parse_from(["best_route", "--stats", "Tanoo"]);
+        let lines = system_lines(&clouds, &routes, &[report], &args);
+        assert_eq!(
+            "Distances: min 1 Mm, max 10 Mm, mean 6 Mm, median 5 Mm, diameter 10 Mm",
+            lines[0]
+        );
+    }
+
+    #[test]
+    fn test_dot_graph() {
+        let cloud = cloud_of(
+            40000002,
+            &[(1, 0.0, 0.0, 5e6), (2, 20e6, 0.0, 5e6), (3, 10e6, 0.0, 5e6)],
+        );
+        let route = cloud.get_best_route();
+        let report = SystemReport {
+            system_id: 30000001,
+            name: "Tanoo".to_string(),
+            security_status: 0.86,
+            clouds: vec![RouteReport::new(&cloud, &route, &cloud.get_ordinal_route())],
+        };
+
+        let dot = dot_graph(&[report], &NumberStyle::Plain, 0);
+        assert!(dot.starts_with("digraph route {"));
+        assert!(dot.ends_with('}'));
+        assert!(dot.contains("subgraph cluster_30000001_0 {"));
+        assert!(dot.contains("label=\"Tanoo 40000002\";"));
+        let nodes = dot.lines().filter(|line| line.contains("pos=")).count();
+        assert_eq!(3, nodes);
+        assert!(dot.contains("3 [label=\"System I - Asteroid Belt 3\", pos=\"10,5!\"];"));
+        let edges = dot
+            .lines()
+            .filter(|line| line.contains(" -> "))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            vec![
+                "\t\t1 -> 3 [label=\"10 Mm\", color=red, penwidth=2];",
+                "\t\t3 -> 2 [label=\"10 Mm\", color=red, penwidth=2];",
+            ],
+            edges
+        );
+        assert_eq!("digraph route {\n}", dot_graph(&[], &NumberStyle::Plain, 0));
+    }
+
+    #[test]
+    fn test_position_arrays() {
+        let position = Position::new(&1.5, &-2.0, &3e12);
+        assert_eq!([1.5, -2.0, 3e12], position.to_array());
+        assert_eq!(position, Position::from_array([1.5, -2.0, 3e12]));
+        assert_eq!(position, Position::from_array(position.to_array()));
+
+        let array: [f64; 3] = position.clone().into();
+        assert_eq!(position, Position::from(array));
+        let converted: Position = [0.0, 0.0, 0.0].into();
+        assert_eq!(Position::default(), converted);
+    }
+
+    #[test]
+    fn test_route_through() {
+        let places = (1..=6)
+            .map(|id| (id, id as f64 * 1e6, 0.0, 0.0))
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        let interior = |route: &[i32], id: i32| {
+            route.contains(&id) && route.first() != Some(&id) && route.last() != Some(&id)
+        };
+
+        for solver in [Solver::Size, Solver::NearestNeighbor, Solver::LinKernighan] {
+            // The free routes of the line end at 1 and 6
+            let options = RouteOptions {
+                solver,
+                through: Some(6),
+                ..RouteOptions::default()
+            };
+            let (distance, route) = cloud.solve(&options);
+            assert_eq!(6, route.len());
+            assert!(interior(&route, 6), "{solver:?}: {route:?}");
+            assert!(approx_eq(distance, cloud.route_distance(&route)));
+
+            let pinned = RouteOptions {
+                start: Some(3),
+                ..options.clone()
+            };
+            let (_, route) = cloud.solve(&pinned);
+            assert_eq!(3, route[0]);
+            assert!(interior(&route, 6), "{solver:?}: {route:?}");
+        }
+
+        // An inner place is left as the solver put it
+        let options = RouteOptions {
+            through: Some(3),
+            ..RouteOptions::default()
+        };
+        assert_eq!(cloud.get_best_route(), cloud.solve(&options));
+    }
+
+    #[test]
+    fn test_auto_solver() {
+        // The x-z positions in Mm
+        let cloud_in_mm = |positions: &[(f64, f64)]| {
+            let places = positions
+                .iter()
+                .zip(1..)
+                .map(|((x, z), id)| (id, x * 1e6, 0.0, z * 1e6))
+                .collect::<Vec<_>>();
+            cloud_of(0, &places)
+        };
+        let options = RouteOptions::default();
+        assert_eq!(Solver::Auto, options.solver);
+        assert_eq!(
+            Solver::Auto,
+            Args::parse_from(["best_route", "Tanoo"]).solver
+        );
+
+        // Small clouds are brute forced
+        let small = cloud_in_mm(&[(0.0, 0.0), (5.0, 1.0), (2.0, 7.0), (9.0, 3.0), (4.0, 4.0)]);
+        assert_eq!(Strategy::Exact, small.strategy(false, &options));
+        assert_eq!(small.get_route(&Solver::Size), small.get_best_route());
+
+        // Evenly spread belts have no gaps to split by
+        let grid = (0..30)
+            .map(|i| ((i % 6) as f64, (i / 6) as f64))
+            .collect::<Vec<(f64, f64)>>();
+        let grid = cloud_in_mm(&grid);
+        assert_eq!(Strategy::Greedy, grid.strategy(false, &options));
+        let (distance, route) = grid.get_best_route();
+        assert_eq!(30, route.iter().unique().count());
+        assert!(!is_shorter(
+            grid.get_route(&Solver::NearestNeighbor).0,
+            distance
+        ));
+
+        // Two grids far apart are routed one after the other
+        let clusters = (0..24)
+            .map(|i| {
+                (
+                    (i % 4) as f64 + if i < 12 { 0.0 } else { 500.0 },
+                    ((i % 12) / 4) as f64,
+                )
+            })
+            .collect::<Vec<(f64, f64)>>();
+        let clusters = cloud_in_mm(&clusters);
+        assert!(matches!(
+            clusters.strategy(false, &options),
+            Strategy::Clustered(_)
+        ));
+        let (distance, route) = clusters.get_best_route();
+        assert_eq!(24, route.iter().unique().count());
+        assert!(approx_eq(distance, clusters.route_distance(&route)));
+        // The far warp is taken once
+        let far = route
+            .iter()
+            .tuple_windows()
+            .filter(|(a, b)| clusters.distance_between(a, b).unwrap() > 100e6)
+            .count();
+        assert_eq!(1, far);
+
+        let pinned = RouteOptions {
+            start: Some(20),
+            ..RouteOptions::default()
+        };
+        let (_, route) = clusters.solve(&pinned);
+        assert_eq!(20, route[0]);
+        assert_eq!(24, route.iter().unique().count());
+    }
+
+    #[tokio::test]
+    async fn test_fake_http_loads() {
+        let fake = std::sync::Arc::new(
+            FakeHttp::default()
+                .respond(
+                    "/universe/systems/30000001/",
+                    200,
+                    include_str!("../tests/fixtures/system.json"),
+                )
+                .respond(
+                    "/universe/asteroid_belts/40000003/",
+                    200,
+                    include_str!("../tests/fixtures/asteroid_belt.json"),
+                )
+                .respond(
+                    "/universe/ids/",
+                    200,
+                    include_str!("../tests/fixtures/universe_ids.json"),
+                ),
+        );
+        let esi = Esi::with_client("http://esi.fake", fake.clone());
+
+        let system = System::load(&esi, &30000001).await.unwrap();
+        assert_eq!("Tanoo", system.name);
+        assert_eq!(3, system.planets.unwrap().len());
+
+        let belt = AsteroidBelt::load(&esi, &40000003).await.unwrap();
+        assert_eq!("Tanoo I - Asteroid Belt 1", belt.name);
+        assert_eq!(30000001, belt.system_id);
+
+        let names = vec!["Tanoo".to_string(), "Jita".to_string()];
+        let universe = Universe::load(&esi, &names).await.unwrap();
+        assert_eq!(2, universe.systems.unwrap().len());
+
+        let requests = fake.requests.lock().unwrap().clone();
+        assert_eq!(3, requests.len());
+        assert_eq!(reqwest::Method::GET, requests[0].method);
+        assert_eq!(None, requests[0].body);
+        assert_eq!(reqwest::Method::POST, requests[2].method);
+        assert_eq!(Some(r#"["Tanoo","Jita"]"#.to_string()), requests[2].body);
+
+        assert!(AsteroidBelt::load(&esi, &40000004).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fake_http_retries() {
+        let fake = std::sync::Arc::new(
+            FakeHttp::default()
+                .respond("/universe/asteroid_belts/40000003/", 503, "")
+                .respond(
+                    "/universe/asteroid_belts/40000003/",
+                    200,
+                    include_str!("../tests/fixtures/asteroid_belt.json"),
+                )
+                .respond("/universe/asteroid_belts/40000004/", 404, ""),
+        );
+        let mut esi = Esi::with_client("http://esi.fake", fake.clone());
+        esi.retry = Retry {
+            attempts: 2,
+            delay: Duration::ZERO,
+            jitter: 0.0,
+        };
+
+        // The server error is retried, the missing belt is not
+        assert!(AsteroidBelt::load(&esi, &40000003).await.is_ok());
+        let err = AsteroidBelt::load(&esi, &40000004).await.unwrap_err();
+        assert!(err.to_string().contains("HTTP status 404"));
+        assert_eq!(3, fake.requests.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_summary_csv() {
+        let cloud_of = |planet_id: i32, xs: &[f64]| {
+            let mut cloud = Cloud::with_planet(&planet_id);
+            for (n, x) in xs.iter().enumerate() {
+                let id = planet_id * 10 + n as i32;
+                cloud.add(
+                    &id,
+                    &format!("Tanoo I - Asteroid Belt {}", n + 1),
+                    &Position::new(x, &0.0, &0.0),
+                );
+            }
+            let route = cloud.get_best_route();
+            RouteReport::new(&cloud, &route, &cloud.get_ordinal_route())
+        };
+        let reports = [
+            SystemReport {
+                system_id: 30000001,
+                name: "Tanoo".to_string(),
+                security_status: 0.8583,
+                clouds: vec![cloud_of(1, &[0.0, 2e6, 7e6]), cloud_of(2, &[0.0, 1e6])],
+            },
+            SystemReport {
+                system_id: 30000002,
+                name: "Odd, \"Name\"".to_string(),
+                security_status: 0.7,
+                clouds: vec![cloud_of(3, &[0.0])],
+            },
+        ];
+
+        let csv = summary_csv(&reports);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(
+            vec![
+                "system,system_id,security,clouds,belts,distance,longest_hop",
+                "Tanoo,30000001,0.86,2,5,8000000,5000000",
+                "\"Odd, \"\"Name\"\"\",30000002,0.70,1,1,0,0",
+            ],
+            lines
+        );
+        assert_eq!(1, summary_csv(&[]).lines().count());
+        let args = Args::parse_from(["best_route", "--summary-csv", "out.csv", "Tanoo"]);
+        assert_eq!(Some("out.csv".to_string()), args.summary_csv);
+    }
+
+    #[test]
+    fn test_concurrency_aimd() {
+        let concurrency = Concurrency::new(4, 8);
+        assert_eq!(4, concurrency.limit());
+        // About a request more per a window of the successes
+        (0..5).for_each(|_| concurrency.success());
+        assert_eq!(5, concurrency.limit());
+        (0..100).for_each(|_| concurrency.success());
+        assert_eq!(8, concurrency.limit());
+
+        concurrency.backoff();
+        assert_eq!(4, concurrency.limit());
+        (0..10).for_each(|_| concurrency.backoff());
+        assert_eq!(1, concurrency.limit());
+        concurrency.success();
+        assert_eq!(2, concurrency.limit());
+
+        assert_eq!(1, Concurrency::new(0, 0).limit());
+        assert_eq!(3, Concurrency::new(10, 3).limit());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_load_all_adapts_to_the_responses() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        let concurrency = Arc::new(Concurrency::new(4, 16));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let ids = (0..60).collect::<Vec<i32>>();
+        let loaded = load_all(&ids, &concurrency, |id| {
+            let (concurrency, in_flight, started) =
+                (concurrency.clone(), in_flight.clone(), started.clone());
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                started.lock().unwrap().push((id, now));
+                // The latencies vary and the loads from 20 to 23 are throttled
+                tokio::time::sleep(Duration::from_millis(10 + (id % 7) as u64 * 15)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                if (20..24).contains(&id) {
+                    concurrency.backoff();
+                } else {
+                    concurrency.success();
+                }
+                Ok(id * 2)
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(60, loaded.len());
+        assert_eq!(Some(&118), loaded.get(&59));
+
+        let started = started.lock().unwrap().clone();
+        let peak = |range: std::ops::Range<i32>| {
+            started
+                .iter()
+                .filter(|(id, _)| range.contains(id))
+                .map(|(_, now)| *now)
+                .max()
+                .unwrap()
+        };
+        assert!(started.iter().all(|(_, now)| *now <= 16));
+        // Grows from 4, halves on every throttled load and grows back
+        assert!(peak(0..20) > 4);
+        assert!(peak(25..30) <= 2);
+        assert!(peak(50..60) > peak(25..30));
+        assert!(concurrency.limit() > 4);
+    }
+
+    #[test]
+    fn test_to_tsplib() {
+        let cloud = cloud_of(
+            40000002,
+            &[
+                (7, 1.5, -2.0, 3e11),
+                (3, 0.0, 0.0, 0.0),
+                (5, 1e6, 2e6, -3e6),
+            ],
+        );
+        let tsp = cloud.to_tsplib();
+
+        let mut header = HashMap::new();
+        let mut nodes = Vec::new();
+        let mut lines = tsp.lines();
+        for line in lines.by_ref() {
+            if line == "NODE_COORD_SECTION" {
+                break;
+            }
+            let (key, value) = line.split_once(" : ").unwrap();
+            header.insert(key, value);
         }
+        for line in lines.by_ref() {
+            if line == "EOF" {
+                break;
+            }
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let n = fields[0].parse::<usize>().unwrap();
+            let position = fields[1..]
+                .iter()
+                .map(|value| value.parse::<f64>().unwrap())
+                .collect::<Vec<f64>>();
+            nodes.push((n, position));
+        }
+        assert_eq!(None, lines.next());
+
+        assert_eq!(Some(&"planet_40000002"), header.get("NAME"));
+        assert_eq!(Some(&"TSP"), header.get("TYPE"));
+        assert_eq!(Some(&"3"), header.get("DIMENSION"));
+        assert_eq!(Some(&"EUC_3D"), header.get("EDGE_WEIGHT_TYPE"));
+        assert_eq!(Some(&"belts 3 5 7"), header.get("COMMENT"));
+        assert_eq!(
+            vec![
+                (1, vec![0.0, 0.0, 0.0]),
+                (2, vec![1e6, 2e6, -3e6]),
+                (3, vec![1.5, -2.0, 3e11]),
+            ],
+            nodes
+        );
     }
 
-    pub fn add(&mut self, id: &i32, name: &String, position: &Position) {
-        let belt = Place::new(id, &name, &position);
+    #[test]
+    fn test_import_route() {
+        let cloud = cloud_of(
+            40000002,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 20e6, 0.0, 0.0),
+                (3, 10e6, 0.0, 0.0),
+                (4, 35e6, 0.0, 0.0),
+            ],
+        );
 
-        for (destination, belt) in &self.places {
-            let distance = Position::distance(&position, &belt.position);
-            debug!("Distance between {} and {} - {}", name, belt.name, distance);
+        let ids = "4, 2\n3 1\n".parse::<ImportedRoute>().unwrap();
+        assert_eq!(ImportedRoute::Ids(vec![4, 2, 3, 1]), ids);
+        let route = cloud.imported(&ids).unwrap().unwrap();
+        let distance = cloud.route_distance(&route);
+        assert!(approx_eq(35e6, distance));
+        let args = Args::parse_from(["best_route", "Tanoo"]);
+        let lines = route_lines(&cloud, (distance, route), &args);
+        assert_eq!(
+            Some(&format!(
+                "The length of the route: {}",
+                fmt(&distance, &NumberStyle::Plain, 0)
+            )),
+            lines.last()
+        );
+        assert_eq!(
+            " 4 Warp to `System I - Asteroid Belt 1` - 10 Mm (cum 35 Mm)",
+            lines[3]
+        );
 
-            self.distances
-                .entry(*id)
-                .or_insert(HashMap::new())
-                .insert(*destination, distance);
+        // The tour of the instance written by to_tsplib, as the LKH writes it
+        let tour = "NAME : planet_40000002.45000000.tour\nTYPE : TOUR\nDIMENSION : 4\n\
+                    TOUR_SECTION\n1\n3\n2\n4\n-1\nEOF\n"
+            .parse::<ImportedRoute>()
+            .unwrap();
+        assert_eq!(
+            ImportedRoute::Tour {
+                planet_id: Some(40000002),
+                nodes: vec![1, 3, 2, 4]
+            },
+            tour
+        );
+        assert_eq!(vec![1, 3, 2, 4], cloud.imported(&tour).unwrap().unwrap());
+        assert!(Cloud::with_planet(&40000007).imported(&tour).is_none());
 
-            self.distances
-                .entry(*destination)
-                .or_insert(HashMap::new())
-                .insert(*id, distance);
+        // Only the permutations of the belts are taken
+        let invalid = |text: &str| {
+            let route = text.parse::<ImportedRoute>().unwrap();
+            cloud.imported(&route).unwrap().unwrap_err().to_string()
+        };
+        assert_eq!("The belt 3 is not visited", invalid("1 2 4"));
+        assert_eq!("The belt 2 is visited more than once", invalid("1 2 3 4 2"));
+        assert_eq!(
+            "The belt 9 is not in the cloud of the planet 40000002",
+            cloud
+                .validate_route(&[1, 2, 3, 4, 9])
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(cloud.imported(&ImportedRoute::Ids(vec![7, 8])).is_none());
+
+        // The ids of a whole system are split by the cloud
+        let outer = cloud_of(40000007, &[(7, 0.0, 0.0, 0.0), (8, 5e6, 0.0, 0.0)]);
+        let system = "8 4 2 3 7 1".parse::<ImportedRoute>().unwrap();
+        assert_eq!(vec![4, 2, 3, 1], cloud.imported(&system).unwrap().unwrap());
+        assert_eq!(vec![8, 7], outer.imported(&system).unwrap().unwrap());
+        assert!("1 two 3".parse::<ImportedRoute>().is_err());
+    }
+
+    #[test]
+    fn test_cloud_merge() {
+        let mut inner = Cloud::with_planet(&40000002);
+        inner.add(
+            &1,
+            "Tanoo I - Asteroid Belt 1",
+            &Position::new(&0.0, &0.0, &0.0),
+        );
+        inner.add(
+            &2,
+            "Tanoo I - Asteroid Belt 2",
+            &Position::new(&3.0, &0.0, &0.0),
+        );
+        let mut outer = Cloud::with_planet(&40000007);
+        outer.add(
+            &3,
+            "Tanoo III - Asteroid Belt 1",
+            &Position::new(&10.0, &0.0, &0.0),
+        );
+        outer.add(
+            &4,
+            "Tanoo III - Asteroid Belt 2",
+            &Position::new(&10.0, &4.0, &0.0),
+        );
+        // The known distances are taken as they are, a recomputed one would be 3 and 4
+        for (cloud, a, b) in [(&mut inner, 1, 2), (&mut outer, 3, 4)] {
+            cloud.distances.get_mut(&a).unwrap().insert(b, 42.0);
+            cloud.distances.get_mut(&b).unwrap().insert(a, 42.0);
         }
 
-        if let Some(old) = self.places.insert(*id, belt) {
-            warn!("The old value for {id} was replaced with: {:?}", old);
+        let merged = inner.merge(&outer);
+        assert_eq!(40000002, merged.planet_id);
+        assert_eq!(vec![1, 2, 3, 4], merged.ids());
+        assert_eq!(Some(42.0), merged.distance_between(&1, &2));
+        assert_eq!(Some(42.0), merged.distance_between(&4, &3));
+        assert!(approx_eq(10.0, merged.distance_between(&1, &3).unwrap()));
+        assert!(approx_eq(
+            merged.distance_between(&2, &4).unwrap(),
+            merged.distance_between(&4, &2).unwrap()
+        ));
+        assert!(approx_eq(
+            65f64.sqrt(),
+            merged.distance_between(&4, &2).unwrap()
+        ));
+        assert!(merged.distances.values().all(|known| known.len() == 3));
+    }
+
+    #[test]
+    fn test_lower_bound() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let places = (1..=rng.gen_range(3..9))
+                .map(|id| {
+                    (
+                        id,
+                        rng.gen_range(0.0..100.0),
+                        rng.gen_range(0.0..10.0),
+                        rng.gen_range(0.0..100.0),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let cloud = cloud_of(0, &places);
+            let options = RouteOptions::default();
+            assert!(cloud.is_exact(&options));
+            let (optimal, _) = cloud.solve(&options);
+            let bound = cloud.lower_bound();
+            assert!(bound > 0.0 && !is_shorter(optimal, bound), "seed {seed}");
         }
+
+        // The tree of a line is the line itself
+        let line = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 30e6, 0.0, 0.0),
+                (3, 10e6, 0.0, 0.0),
+                (4, 60e6, 0.0, 0.0),
+            ],
+        );
+        assert!(approx_eq(60e6, line.lower_bound()));
+        assert_eq!(0.0, Cloud::new().lower_bound());
+        assert!(!line.is_exact(&RouteOptions {
+            solver: Solver::NearestNeighbor,
+            ..RouteOptions::default()
+        }));
+
+        let mut report = RouteReport::new(&line, &(66e6, vec![1, 3, 2, 4]), &(66e6, vec![]));
+        report.lower_bound = Some(line.lower_bound());
+        let lines = system_lines(
+            std::slice::from_ref(&line),
+            &[((0.0, vec![]), (66e6, vec![1, 3, 2, 4]))],
+            &[report],
+            &Args::parse_from(["best_route", "Tanoo"]),
+        );
+        assert_eq!(
+            Some(&"Lower bound 60 Mm, within 10% of optimal".to_string()),
+            lines.last()
+        );
     }
 
-    pub fn distance_between(&self, a: &i32, b: &i32) -> Option<f64> {
-        if let Some(ref value) = self.distances.get(a) {
-            return value.get(b).cloned();
+    #[test]
+    fn test_cloud_mst() {
+        // 1 - 2 - 3 on a line, 4 above 2 and 5 far to the right of 3
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 3.0, 0.0, 0.0),
+                (3, 7.0, 0.0, 0.0),
+                (4, 3.0, 2.0, 0.0),
+                (5, 17.0, 0.0, 0.0),
+            ],
+        );
+        let mst = cloud.mst();
+        assert_eq!(
+            vec![(1, 2), (2, 4), (2, 3), (3, 5)],
+            mst.iter().map(|(a, b, _)| (*a, *b)).collect::<Vec<_>>()
+        );
+        for ((_, _, distance), expected) in mst.iter().zip([3.0, 2.0, 4.0, 10.0]) {
+            assert!(approx_eq(expected, *distance));
         }
-        return None;
+        assert!(approx_eq(19.0, cloud.lower_bound()));
+
+        assert!(Cloud::new().mst().is_empty());
+        let mut single = Cloud::new();
+        single.add(&1, "System I - Asteroid Belt 1", &Position::default());
+        assert!(single.mst().is_empty());
     }
 
-    fn route_distance(&self, route: &Vec<&i32>) -> f64 {
-        let mut distance = 0.0;
-        route.iter().reduce(|a, b| {
-            distance += self.distance_between(&a, &b).unwrap_or(0.0);
-            return b;
-        });
-        return distance;
+    #[test]
+    fn test_add_waypoints() {
+        let point = "Safe spot: 2e6, 0, 3e6".parse::<Waypoint>().unwrap();
+        assert_eq!("Safe spot", point.name);
+        assert_eq!(Position::new(&2e6, &0.0, &3e6), point.position);
+        assert_eq!(
+            "Spot: A:1,2,3",
+            "Spot: A:1,2,3:1,2,3".parse::<Waypoint>().unwrap().name
+        );
+        assert!("1,2,3".parse::<Waypoint>().is_err());
+        assert!("Spot:1,2".parse::<Waypoint>().is_err());
+        assert!(":1,2,3".parse::<Waypoint>().is_err());
+
+        let mut inner = Cloud::with_planet(&40000002);
+        inner.add(
+            &1,
+            "Tanoo I - Asteroid Belt 1",
+            &Position::new(&0.0, &0.0, &0.0),
+        );
+        inner.add(
+            &2,
+            "Tanoo I - Asteroid Belt 2",
+            &Position::new(&4e6, &0.0, &0.0),
+        );
+        let mut outer = Cloud::with_planet(&40000007);
+        outer.add(
+            &3,
+            "Tanoo III - Asteroid Belt 1",
+            &Position::new(&90e6, &0.0, &0.0),
+        );
+        let mut clouds = vec![inner, outer];
+        add_waypoints(&mut clouds, &[point]);
+
+        assert_eq!(vec![-1, 1, 2], clouds[0].ids());
+        assert_eq!(vec![3], clouds[1].ids());
+        assert_eq!(Some("Safe spot".to_string()), clouds[0].get_name(&-1));
+        assert!(approx_eq(
+            13e12_f64.sqrt(),
+            clouds[0].distance_between(&-1, &1).unwrap()
+        ));
+        assert!(approx_eq(
+            13e12_f64.sqrt(),
+            clouds[0].distance_between(&2, &-1).unwrap()
+        ));
+        // The point lies between the belts, off the line
+        let (distance, route) = clouds[0].solve(&RouteOptions::default());
+        assert_eq!(3, route.len());
+        assert_eq!(-1, route[1]);
+        assert!(approx_eq(2.0 * 13e12_f64.sqrt(), distance));
     }
 
-    fn get_ids_sorted_by_name(&self) -> Vec<i32> {
-        let mut places = self.places.values().cloned().collect::<Vec<Place>>();
-        places.sort_by(|a, b| {
-            if a.cloud_number == b.cloud_number {
-                a.belt_number.cmp(&b.belt_number)
-            } else {
-                a.cloud_number.cmp(&b.cloud_number)
+    #[test]
+    fn test_compare_systems() {
+        // The belts at the megameters on a line, routed along it
+        let system = |system_id: i32, name: &str, security_status: f32, xs: &[f64]| {
+            let mut cloud = Cloud::with_planet(&(system_id + 1));
+            for (n, x) in xs.iter().enumerate() {
+                cloud.add(
+                    &(n as i32 + 1),
+                    &format!("{name} I - Asteroid Belt {}", n + 1),
+                    &Position::new(&(x * 1e6), &0.0, &0.0),
+                );
             }
-        });
-        places.into_iter().map(|belt| belt.id).collect::<Vec<i32>>()
-    }
+            let route = cloud.get_best_route();
+            SystemReport {
+                system_id,
+                name: name.to_string(),
+                security_status,
+                clouds: vec![RouteReport::new(&cloud, &route, &cloud.get_ordinal_route())],
+            }
+        };
+        let reports = [
+            system(
+                30000001,
+                "Remote",
+                0.25,
+                &[0.0, 20.0, 40.0, 60.0, 80.0, 100.0, 110.0, 120.0],
+            ),
+            system(30000002, "Tanoo", 0.75, &[0.0, 2.0, 4.0, 6.0, 8.0]),
+            system(30000003, "Tiny", 0.5, &[0.0, 1.0]),
+        ];
 
-    pub fn get_ordinal_route(&self) -> (f64, Vec<i32>) {
-        let points = self.get_ids_sorted_by_name();
-        let refs = points.iter().collect::<Vec<&i32>>();
-        (self.route_distance(&refs), points)
+        let weights = "1, 0.1, 10".parse::<ScoreWeights>().unwrap();
+        assert!(approx_eq(1.0 * 5.0 - 0.8 + 7.5, weights.score(&reports[1])));
+        assert!(approx_eq(-1.5, weights.score(&reports[0])));
+        let lines = ranking_lines(&reports, &weights, &NumberStyle::Plain, 0);
+        assert_eq!(
+            vec![
+                "Rank System Security Belts     Distance    Score",
+                "   1 Tanoo      0.75     5         8 Mm    11.70",
+                "   2 Tiny       0.50     2         1 Mm     6.90",
+                "   3 Remote     0.25     8       120 Mm    -1.50",
+            ],
+            lines
+        );
+
+        // Only the belts count
+        let belts = "1,0,0".parse::<ScoreWeights>().unwrap();
+        let names = ranking_lines(&reports, &belts, &NumberStyle::Plain, 0)
+            .iter()
+            .skip(1)
+            .map(|line| line.split_whitespace().nth(1).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["Remote", "Tanoo", "Tiny"], names);
+
+        assert!("1,2".parse::<ScoreWeights>().is_err());
+        let args = Args::parse_from(["best_route", "--compare-systems", "Tanoo"]);
+        assert!(args.compare_systems);
+        assert_eq!(weights, args.score_weights);
     }
 
-    pub fn get_best_route(&self) -> (f64, Vec<i32>) {
-        let points = self.get_ids_sorted_by_name();
-        if points.is_empty() {
-            (0.0, vec![])
-        } else if 1 == points.len() {
-            (0.0, points.clone())
-        } else if 2 == points.len() {
-            let refs = points.iter().collect::<Vec<&i32>>();
-            (self.route_distance(&refs), points.clone())
-        } else if points.len() < 10 {
-            self.brute_force(&points)
-        } else {
-            self.lazzy_walker(&points)
-        }
+    #[test]
+    fn test_from_center() {
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, 10e6, 0.0, 0.0),
+                (3, 4e6, 0.0, 1e6),
+                (4, 30e6, 0.0, 0.0),
+            ],
+        );
+        assert_eq!(Some(Position::new(&11e6, &0.0, &0.25e6)), cloud.centroid());
+        assert_eq!(Some(2), cloud.nearest_to_centroid());
+        assert_eq!(None, Cloud::new().nearest_to_centroid());
+
+        let args = Args::parse_from(["best_route", "--from-center", "Tanoo"]);
+        assert!(args.from_center);
+        let options = RouteOptions {
+            start: cloud.nearest_to_centroid(),
+            ..RouteOptions::default()
+        };
+        let (_, route) = cloud.solve(&options);
+        assert_eq!(Some(&2), route.first());
+        assert!(Args::try_parse_from([
+            "best_route",
+            "--from-center",
+            "--route-from",
+            "1",
+            "Tanoo"
+        ])
+        .is_err());
     }
 
-    fn lazzy_walker(&self, points: &Vec<i32>) -> (f64, Vec<i32>) {
-        let mut starts = LinkedList::new();
-        for point in points {
-            starts.push_back(point);
-        }
+    /// Writes the fixtures to the cache and reads them back
+    fn check_cache(cache: &dyn Cache) -> (AsteroidBelt, System) {
+        let belt: AsteroidBelt =
+            serde_json::from_str(include_str!("../tests/fixtures/asteroid_belt.json")).unwrap();
+        let system: System =
+            serde_json::from_str(include_str!("../tests/fixtures/system.json")).unwrap();
+        assert_eq!(None, cache.belt(&40000003));
+        assert!(!cache.has_belt(&40000003));
+        assert_eq!(None, cache.system(&30000001));
+        assert_eq!(None, cache.system_id("Tanoo"));
 
-        let mut min_dist = f64::MAX;
-        let mut min_route = Vec::new();
-        let mut count = points.len();
-        while count > 0 {
-            if let Some(point) = starts.pop_front() {
-                let tail = starts.iter().cloned().cloned().collect::<Vec<i32>>();
-                let (dist, route) = self.lazzy_walker_impl(vec![*point], tail);
-                if dist < min_dist {
-                    min_dist = dist;
-                    min_route = route;
-                }
-                starts.push_back(point);
-            }
+        cache.put_belt(&40000003, &belt).unwrap();
+        cache.put_system(&30000001, &system).unwrap();
+        cache.put_system_id("Tanoo", &30000001).unwrap();
+        cache.put_system_id("Amarr", &30002187).unwrap();
+        assert!(cache.has_belt(&40000003));
+        assert_eq!(Some(30000001), cache.system_id("tanoo"));
+        assert_eq!(Some(30002187), cache.system_id("AMARR"));
 
-            count -= 1;
-        }
+        let renamed = AsteroidBelt {
+            name: "Tanoo I - Asteroid Belt 2".to_string(),
+            ..belt.clone()
+        };
+        cache.put_belt(&40000004, &renamed).unwrap();
+        assert_eq!(Some(renamed), cache.belt(&40000004));
 
-        return (min_dist, min_route);
+        assert_eq!(None, cache.route("0123456789abcdef"));
+        let route = CachedRoute::new(&[40000003, 40000004]);
+        cache.put_route("0123456789abcdef", &route).unwrap();
+        assert_eq!(Some(route), cache.route("0123456789abcdef"));
+        (
+            cache.belt(&40000003).unwrap(),
+            cache.system(&30000001).unwrap(),
+        )
     }
 
-    fn lazzy_walker_impl(&self, mut route: Vec<i32>, mut points: Vec<i32>) -> (f64, Vec<i32>) {
-        if points.is_empty() {
-            let refs = route.iter().collect::<Vec<&i32>>();
-            return (self.route_distance(&refs), route);
-        }
+    #[test]
+    fn test_json_cache() {
+        let dir = std::env::temp_dir().join(format!("best_route_json_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let cache = JsonCache::new(&dir).unwrap();
+        let (belt, system) = check_cache(&cache);
+        assert_eq!("Tanoo I - Asteroid Belt 1", belt.name);
+        assert_eq!(3, system.planets.unwrap().len());
 
-        if let Some(point) = route.iter().last() {
-            points.sort_by(|a, b| {
-                let d_a = self.distance_between(point, a).unwrap();
-                let d_b = self.distance_between(point, b).unwrap();
-                d_b.partial_cmp(&d_a).unwrap()
-            });
-            if let Some(closest) = points.pop() {
-                route.push(closest);
-            }
-        }
-        return self.lazzy_walker_impl(route, points);
+        // A broken file is loaded again
+        std::fs::write(dir.join("40000003.json"), "{").unwrap();
+        assert_eq!(None, cache.belt(&40000003));
+        assert!(!cache.has_belt(&40000003));
+        std::fs::remove_dir_all(&dir).ok();
     }
 
-    fn brute_force(&self, points: &Vec<i32>) -> (f64, Vec<i32>) {
-        let mut minimal = f64::MAX;
-        let mut route = Vec::new();
-        let mut calculated = HashSet::new();
-        for path in points.iter().permutations(points.len()) {
-            if !calculated.contains(&path) {
-                let mut reversed = path.clone();
-                reversed.reverse();
-                calculated.insert(reversed);
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_cache() {
+        let dir = std::env::temp_dir().join(format!("best_route_sqlite_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = check_cache(&JsonCache::new(&dir.join("json")).unwrap());
+        let path = dir.join("cache.db");
+        let sqlite = check_cache(&SqliteCache::open(&path).unwrap());
+        assert_eq!(json, sqlite);
 
-                let distance = self.route_distance(&path);
-                if distance < minimal {
-                    minimal = distance;
-                    route = path.into_iter().cloned().collect();
-                }
-            }
-        }
+        // The data outlives the connection
+        let reopened = SqliteCache::open(&path).unwrap();
+        assert_eq!(Some(sqlite.0), reopened.belt(&40000003));
+        assert_eq!(Some(sqlite.1), reopened.system(&30000001));
+        assert_eq!(Some(30000001), reopened.system_id("TANOO"));
+        drop(reopened);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_detour_ratio() {
+        // The x-z positions in Mm
+        let cloud_in_mm = |positions: &[(f64, f64)]| {
+            let places = positions
+                .iter()
+                .zip(1..)
+                .map(|((x, z), id)| (id, x * 1e6, 0.0, z * 1e6))
+                .collect::<Vec<_>>();
+            cloud_of(0, &places)
+        };
+        let collinear = cloud_in_mm(&[(0.0, 0.0), (7.0, 0.0), (3.0, 0.0), (12.0, 0.0)]);
+        let (_, route) = collinear.get_best_route();
+        assert!(approx_eq(1.0, collinear.detour_ratio(&route).unwrap()));
+
+        let scattered = cloud_in_mm(&[
+            (0.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (10.0, 0.0),
+            (5.0, 5.0),
+        ]);
+        let (_, route) = scattered.get_best_route();
+        assert!(scattered.detour_ratio(&route).unwrap() > 1.5);
+        // Back where it started: over the diameter
+        let ratio = collinear.detour_ratio(&[1, 4, 1]).unwrap();
+        assert!(approx_eq(2.0, ratio));
+        assert_eq!(None, collinear.detour_ratio(&[]));
+        assert_eq!(None, cloud_in_mm(&[(1.0, 1.0)]).detour_ratio(&[1]));
 
-        return (minimal, route);
+        let args = Args::parse_from(["best_route", "--route-metric-summary", "Tanoo"]);
+        let route = collinear.get_best_route();
+        let report = RouteReport::new(&collinear, &route, &collinear.get_ordinal_route());
+        let lines = system_lines(
+            std::slice::from_ref(&collinear),
+            &[(collinear.get_ordinal_route(), route)],
+            &[report],
+            &args,
+        );
+        assert_eq!(Some(&"Detour ratio: 1.00".to_string()), lines.last());
     }
-}
 
-async fn load_system_asteroids(system: &System) -> anyhow::Result<Vec<Cloud>> {
-    let mut clouds = Vec::new();
-    if let Some(ref planets) = system.planets {
-        for planet in planets {
-            let mut cloud = Cloud::new();
-            if let Some(ref ids) = planet.asteroid_belts {
-                for id in ids {
-                    let belt = AsteroidBelt::load(id).await?;
-                    println!("Belt: {id} - {}: {}", belt.name, belt.position);
-                    cloud.add(id, &belt.name, &belt.position);
-                }
-            }
-            if !cloud.places.is_empty() {
-                clouds.push(cloud);
-            }
+    #[test]
+    fn test_broken_positions() {
+        let broken = Position::new(&f64::NAN, &0.0, &0.0);
+        assert!(!broken.is_finite());
+        assert!(!Position::new(&0.0, &f64::INFINITY, &0.0).is_finite());
+        assert!(Position::new(&1.0, &2.0, &3.0).is_finite());
+
+        let err = Place::checked(
+            &3,
+            "Tanoo I - Asteroid Belt 3",
+            &broken,
+            &NameTemplate::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("(3) is at the broken position"));
+
+        let mut cloud = Cloud::new();
+        cloud.add(
+            &1,
+            "Tanoo I - Asteroid Belt 1",
+            &Position::new(&0.0, &0.0, &0.0),
+        );
+        cloud.add(
+            &2,
+            "Tanoo I - Asteroid Belt 2",
+            &Position::new(&5.0, &0.0, &0.0),
+        );
+        cloud.add(&3, "Tanoo I - Asteroid Belt 3", &broken);
+        let places = vec![
+            Place::new(
+                &4,
+                "Tanoo I - Asteroid Belt 4",
+                &Position::new(&2.0, &0.0, &0.0),
+            )
+            .unwrap(),
+            Place::new(
+                &5,
+                "Tanoo I - Asteroid Belt 5",
+                &Position::new(&0.0, &0.0, &f64::NEG_INFINITY),
+            )
+            .unwrap(),
+        ];
+        assert!(cloud
+            .check_places(&places)
+            .unwrap_err()
+            .to_string()
+            .starts_with("5 is at the broken position"));
+        cloud.add_places(places);
+        assert_eq!(vec![1, 2, 4], cloud.ids());
+        for solver in Solver::value_variants() {
+            let (distance, route) = cloud.get_route(solver);
+            assert!(approx_eq(5.0, distance), "{solver:?}");
+            assert_eq!(3, route.len());
         }
     }
-    Ok(clouds)
-}
 
-fn fmt(distance: &f64) -> String {
-    format!("{} Mm", (distance / 1000000.0).round())
-}
+    #[test]
+    fn test_best_route_named() {
+        let mut cloud = cloud_of(
+            0,
+            &[(1, 0.0, 0.0, 0.0), (2, 9.0, 0.0, 0.0), (3, 4.0, 0.0, 0.0)],
+        );
+        let (distance, route) = cloud.get_best_route();
+        let (named_distance, named) = cloud.best_route_named();
+        assert_eq!(distance, named_distance);
+        assert_eq!(route, named.iter().map(|(id, _)| *id).collect::<Vec<i32>>());
+        assert_eq!(
+            vec![
+                "System I - Asteroid Belt 1",
+                "System I - Asteroid Belt 3",
+                "System I - Asteroid Belt 2"
+            ],
+            named
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect::<Vec<&str>>()
+        );
 
-fn display_route(cloud: &Cloud, (minimum, route): (f64, Vec<i32>)) {
-    let mut step = 1;
-    if 1 == route.len() {
-        let id = route[0];
-        let name = cloud.get_name(&id).unwrap_or_default();
-        println!("{:>2} Warp to `{name}`", step);
-    } else {
-        let mut first_time = true;
-        route.iter().reduce(|a, b| {
-            let dist = cloud.distance_between(&a, &b).unwrap_or(0.0);
-            let name_a = cloud.get_name(a).unwrap_or_default();
-            let name_b = cloud.get_name(b).unwrap_or_default();
-            if first_time {
-                println!("{:>2} Warp to `{name_a}`", step);
-                first_time = false;
-                step += 1;
-            }
+        // A place without a name is named by its id
+        cloud.places.get_mut(&3).unwrap().name.clear();
+        let (_, named) = cloud.best_route_named();
+        assert_eq!((3, "3".to_string()), named[1]);
+    }
 
-            println!("{:>2} Warp to `{name_b}` - {}", step, fmt(&dist));
-            step += 1;
-            return b;
+    #[test]
+    fn test_three_opt() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // The route along the places is as short as the 2-opt and the or-opt get it
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 19.0, 0.0, 6.0),
+                (2, 14.0, 0.0, 6.0),
+                (3, 16.0, 0.0, 15.0),
+                (4, 15.0, 0.0, 15.0),
+                (5, 10.0, 0.0, 18.0),
+                (6, 7.0, 0.0, 14.0),
+                (7, 1.0, 0.0, 18.0),
+                (8, 3.0, 0.0, 14.0),
+                (9, 6.0, 0.0, 0.0),
+            ],
+        );
+        let route = (1..=9).collect::<Vec<i32>>();
+        let mut local = route.clone();
+        assert!(!cloud.two_opt(&mut local, 0, None));
+        assert!(!cloud.or_opt(&mut local, 0));
+        let mut three = route.clone();
+        assert!(cloud.three_opt(&mut three, 0, None));
+        assert!(is_shorter(
+            cloud.route_distance(&three) + 5.0,
+            cloud.route_distance(&route)
+        ));
+        assert_eq!(route, three.iter().cloned().sorted().collect::<Vec<i32>>());
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let places = (1..=12)
+                .map(|id| {
+                    (
+                        id,
+                        rng.gen_range(0.0..100.0),
+                        0.0,
+                        rng.gen_range(0.0..100.0),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let cloud = cloud_of(0, &places);
+            let (_, route) = cloud.get_route(&Solver::NearestNeighbor);
+            let mut polished = route.clone();
+            cloud.three_opt(&mut polished, 1, None);
+            assert!(
+                !is_shorter(
+                    cloud.route_distance(&route),
+                    cloud.route_distance(&polished)
+                ),
+                "seed {seed}"
+            );
+            assert_eq!(route[0], polished[0]);
+            assert_eq!(12, polished.iter().unique().count());
+        }
+
+        let args = Args::parse_from(["best_route", "--polish", "3opt", "Tanoo"]);
+        assert_eq!(Some(Polish::ThreeOpt), args.polish);
+        let options = RouteOptions {
+            solver: Solver::NearestNeighbor,
+            polish: args.polish,
+            start: Some(1),
+            ..RouteOptions::default()
+        };
+        let (distance, polished) = cloud.solve(&options);
+        assert_eq!(1, polished[0]);
+        assert!(approx_eq(cloud.route_distance(&polished), distance));
+        let (unpolished, _) = cloud.solve(&RouteOptions {
+            polish: None,
+            ..options
         });
-        println!("The length of the route: {}", fmt(&minimum));
+        assert!(!is_shorter(unpolished, distance));
     }
-}
-
-async fn make_route(id: &i32) -> anyhow::Result<()> {
-    let system = System::load(id).await?;
-    info!("system_name: {}", system.name);
 
-    let clouds = load_system_asteroids(&system).await?;
-    info!("Clouds: {}", clouds.len());
+    #[test]
+    fn test_belt_order_hint() {
+        // 2 and 3 are as far from 1 on its both sides
+        let cloud = cloud_of(
+            0,
+            &[
+                (1, 0.0, 0.0, 0.0),
+                (2, -10.0, 0.0, 0.0),
+                (3, 10.0, 0.0, 0.0),
+            ],
+        );
+        let route = |start: Option<i32>, hint: &[i32]| {
+            cloud
+                .solve(&RouteOptions {
+                    solver: Solver::NearestNeighbor,
+                    start,
+                    hint: hint.to_vec(),
+                    ..RouteOptions::default()
+                })
+                .1
+        };
+        assert_eq!(vec![1, 3, 2], route(Some(1), &[3, 2]));
+        assert_eq!(vec![1, 2, 3], route(Some(1), &[2]));
+        assert_eq!(vec![3, 1, 2], route(None, &[3]));
+        assert_eq!(vec![2, 1, 3], route(None, &[2, 3]));
+        // The hint doesn't override the nearest neighbor
+        assert_eq!(vec![2, 1, 3], route(Some(2), &[3]));
 
-    println!("\n\t-=[Ordinal route]=-");
-    for cloud in &clouds {
-        display_route(&cloud, cloud.get_ordinal_route());
+        let args = Args::parse_from([
+            "best_route",
+            "--belt-order-hint",
+            "3,System I - Asteroid Belt 2",
+            "Tanoo",
+        ]);
+        let hint = args
+            .belt_order_hint
+            .iter()
+            .filter_map(|key| cloud.find(key))
+            .collect::<Vec<i32>>();
+        assert_eq!(vec![3, 2], hint);
     }
 
-    println!("\n\t-=[Shortest route]=-");
-    for cloud in &clouds {
-        display_route(&cloud, cloud.get_best_route());
+    #[test]
+    fn test_system_space() {
+        let system = |system_id: i32, name: &str| System {
+            system_id,
+            name: name.to_string(),
+            ..System::default()
+        };
+        assert_eq!(Space::Known, system(30000001, "Tanoo").space());
+        assert_eq!(Space::Known, system(30000142, "Jita").space());
+        assert_eq!(Space::Wormhole, system(31000005, "J105443").space());
+        assert_eq!(Space::Wormhole, system(0, "J105443").space());
+        assert_eq!(Space::Wormhole, system(31000005, "Thera").space());
+        assert_eq!(Space::Abyssal, system(32000001, "AD001").space());
     }
-    Ok(())
-}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
-    let args: Vec<String> = env::args().collect();
+    #[test]
+    fn test_iter_places() {
+        let mut cloud = Cloud::new();
+        for (id, name) in [
+            (7, "Tanoo III - Asteroid Belt 1"),
+            (3, "Tanoo I - Asteroid Belt 2"),
+            (5, "Tanoo I - Asteroid Belt 10"),
+            (4, "Tanoo I - Asteroid Belt 1"),
+        ] {
+            cloud.add(&id, name, &Position::new(&(id as f64), &0.0, &0.0));
+        }
 
-    if let Some((cmd, names_ref)) = args.split_first() {
-        let names = names_ref.to_vec();
-        if names.is_empty() {
-            println!("Usage\n\t{} <EveSystemName>", cmd);
-        } else {
-            let universe = Universe::load(&names);
+        let mut visited = cloud
+            .iter_places()
+            .map(|(id, place)| {
+                assert_eq!(*id, place.id);
+                *id
+            })
+            .collect::<Vec<i32>>();
+        visited.sort();
+        assert_eq!(vec![3, 4, 5, 7], visited);
 
-            if let Some(systems) = universe.await?.systems {
-                for obj in &systems {
-                    info!("id: {} - {}", obj.id, obj.name);
-                    make_route(&obj.id).await?;
-                }
-            }
-        }
+        let sorted = cloud
+            .places_sorted()
+            .iter()
+            .map(|place| place.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            vec![
+                "Tanoo I - Asteroid Belt 1",
+                "Tanoo I - Asteroid Belt 2",
+                "Tanoo I - Asteroid Belt 10",
+                "Tanoo III - Asteroid Belt 1"
+            ],
+            sorted
+        );
+        assert_eq!(vec![4, 3, 5, 7], cloud.get_ordinal_route().1);
+        assert!(Cloud::new().places_sorted().is_empty());
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_report_schema_version() {
+        let mut cloud = Cloud::with_planet(&40000002);
+        cloud.add(&1, "Tanoo I - Asteroid Belt 1", &Position::default());
+        let route = cloud.get_best_route();
+        let report = SystemReport {
+            system_id: 30000001,
+            name: "Tanoo".to_string(),
+            security_status: 0.8,
+            clouds: vec![RouteReport::new(&cloud, &route, &route)],
+        };
+        let text = serde_json::to_string(&[&report]).unwrap();
+        assert!(text.contains(r#""schema_version":1"#));
+        assert_eq!(vec![report.clone()], parse_reports(&text).unwrap());
 
-#[cfg(test)]
-mod tests {
-    // Note this useful idiom: importing names from outer (for mod tests) scope.
-    use super::*;
+        // The reports before the versioning
+        let unversioned = text.replace(r#""schema_version":1,"#, "");
+        assert_eq!(
+            0,
+            parse_reports(&unversioned).unwrap()[0].clouds[0].schema_version
+        );
+
+        // A newer schema fails by its version even if its fields don't parse
+        let future = text
+            .replace(r#""schema_version":1"#, r#""schema_version":7"#)
+            .replace(r#""distance""#, r#""length""#);
+        assert_eq!(
+            "The report has the schema version 7, this build reads up to 1",
+            parse_reports(&future).unwrap_err().to_string()
+        );
+        assert!(parse_reports("[{}]").is_err());
+    }
 
     #[test]
-    fn test_cloud() {
+    fn test_cloud_is_symmetric() {
         let mut cloud = Cloud::new();
-        assert_eq!(cloud.distance_between(&0, &1), None);
-
+        assert!(cloud.is_symmetric());
         cloud.add(
             &1,
-            &String::from("System I - Asteroid Belt 1"),
+            "Tanoo I - Asteroid Belt 1",
             &Position::new(&0.0, &0.0, &0.0),
         );
         cloud.add(
             &2,
-            &String::from("System I - Asteroid Belt 2"),
-            &Position::new(&1.0, &0.0, &0.0),
+            "Tanoo I - Asteroid Belt 2",
+            &Position::new(&3.0, &4.0, &0.0),
         );
+        cloud.add(
+            &3,
+            "Tanoo I - Asteroid Belt 3",
+            &Position::new(&6.0, &8.0, &0.0),
+        );
+        assert!(cloud.is_symmetric());
 
-        assert_eq!(cloud.distance_between(&1, &2), Some(1.0));
-        assert_eq!(cloud.distance_between(&2, &1), Some(1.0));
+        let mut corrupted = cloud.clone();
+        corrupted.distances.get_mut(&1).unwrap().insert(2, 6.0);
+        assert!(!corrupted.is_symmetric());
+
+        let mut missing = cloud.clone();
+        missing.distances.get_mut(&3).unwrap().remove(&1);
+        assert!(!missing.is_symmetric());
     }
 
     #[test]
-    fn test_cloud_get_best_route() {
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Asymmetric distances")]
+    fn test_solve_asymmetric_cloud() {
         let mut cloud = Cloud::new();
-        assert_eq!((0.0, vec![]), cloud.get_best_route());
-
         cloud.add(
             &1,
-            &String::from("System I - Asteroid Belt 1"),
+            "Tanoo I - Asteroid Belt 1",
             &Position::new(&0.0, &0.0, &0.0),
         );
-        assert_eq!((0.0, vec![1]), cloud.get_best_route());
+        cloud.add(
+            &2,
+            "Tanoo I - Asteroid Belt 2",
+            &Position::new(&3.0, &4.0, &0.0),
+        );
+        cloud.distances.get_mut(&2).unwrap().insert(1, 1.0);
+        cloud.solve(&RouteOptions::default());
+    }
+
+    #[test]
+    fn test_solver_chain() {
+        let places = (1..=8)
+            .map(|id| {
+                let x = (id as f64 * 7.0) % 11.0;
+                let y = (id as f64 * 3.0) % 5.0;
+                (id, x, y, 0.0)
+            })
+            .collect::<Vec<_>>();
+        let cloud = cloud_of(0, &places);
+        let points = cloud.get_ids_sorted_by_name();
+        let chain = "exact,nearest-insertion,3opt"
+            .split(',')
+            .map(|stage| Stage::from_str(stage, false).unwrap())
+            .collect::<Vec<_>>();
+        let options = RouteOptions {
+            chain: chain.clone(),
+            ..RouteOptions::default()
+        };
+
+        // The exact stage solves the small cloud and ends the chain
+        let (route, ran) = cloud.chain(&points, None, &options);
+        assert_eq!(vec![Stage::Exact], ran);
+        assert!(approx_eq(cloud.get_best_route().0, route.0));
+        assert!(cloud.is_exact(&options));
+
+        // The large one is built by the nearest insertion and improved by the 3-opt
+        let large = RouteOptions {
+            brute_threshold: 5,
+            ..options.clone()
+        };
+        let (route, ran) = cloud.chain(&points, None, &large);
+        assert_eq!(vec![Stage::NearestInsertion, Stage::ThreeOpt], ran);
+        let (inserted, _) = cloud.nearest_insertion(&points, None);
+        assert!(!is_shorter(inserted, route.0));
+        assert!(!cloud.is_exact(&large));
+        assert_eq!(route, cloud.solve(&large));
+
+        // The local search has no route to improve, the nearest neighbor routes the cloud
+        let polish = RouteOptions {
+            chain: vec![Stage::TwoOpt],
+            ..RouteOptions::default()
+        };
+        let (route, ran) = cloud.chain(&points, None, &polish);
+        assert!(ran.is_empty());
+        assert_eq!(cloud.lazzy_walker(&points, None), route);
 
+        // The stages after the deadline are skipped
+        let late = RouteOptions {
+            deadline: Some(std::time::Instant::now()),
+            ..large
+        };
+        let (route, ran) = cloud.chain(&points, None, &late);
+        assert!(ran.is_empty());
+        assert_eq!(8, route.1.len());
+    }
+
+    #[test]
+    fn test_route_cache_ttl() {
+        let dir = std::env::temp_dir().join(format!("best_route_routes_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let cache = JsonCache::new(&dir).unwrap();
+        let mut cloud = Cloud::with_planet(&40000002);
+        cloud.add(
+            &1,
+            "Tanoo I - Asteroid Belt 1",
+            &Position::new(&0.0, &0.0, &0.0),
+        );
         cloud.add(
             &2,
-            &String::from("System I - Asteroid Belt 2"),
+            "Tanoo I - Asteroid Belt 2",
             &Position::new(&1.0, &0.0, &0.0),
         );
-        assert_eq!(1.0, cloud.get_best_route().0);
-
         cloud.add(
             &3,
-            &String::from("System I - Asteroid Belt 3"),
+            "Tanoo I - Asteroid Belt 3",
             &Position::new(&2.0, &0.0, &0.0),
         );
-        assert_eq!(2.0, cloud.get_best_route().0);
-
-        cloud.add(
-            &4,
-            &String::from("System I - Asteroid Belt 4"),
+        let options = RouteOptions::default();
+        let key = route_key(&cloud, &options);
+        assert_eq!(key, route_key(&cloud.clone(), &options));
+        let late = RouteOptions {
+            deadline: Some(std::time::Instant::now()),
+            ..options.clone()
+        };
+        assert_eq!(key, route_key(&cloud, &late));
+        let other = RouteOptions {
+            solver: Solver::NearestInsertion,
+            ..options.clone()
+        };
+        assert_ne!(key, route_key(&cloud, &other));
+        let mut moved = cloud.clone();
+        moved.add(
+            &3,
+            "Tanoo I - Asteroid Belt 3",
             &Position::new(&3.0, &0.0, &0.0),
         );
-        assert_eq!(3.0, cloud.get_best_route().0);
+        assert_ne!(key, route_key(&moved, &options));
+
+        // The fresh route is reused as it was kept
+        assert_eq!(None, fresh_route(&cache, &cloud, &key, 60));
+        cache
+            .put_route(&key, &CachedRoute::new(&[2, 1, 3]))
+            .unwrap();
+        assert_eq!(Some(vec![2, 1, 3]), fresh_route(&cache, &cloud, &key, 60));
+
+        // The expired one is ignored
+        let expired = CachedRoute {
+            solved_at: unix_time() - 61,
+            route: vec![2, 1, 3],
+        };
+        assert!(!expired.is_fresh(60, unix_time()));
+        assert!(expired.is_fresh(61, expired.solved_at + 61));
+        cache.put_route(&key, &expired).unwrap();
+        assert_eq!(None, fresh_route(&cache, &cloud, &key, 60));
+
+        // As well as the one which doesn't fit the cloud
+        cache.put_route(&key, &CachedRoute::new(&[1, 2])).unwrap();
+        assert_eq!(None, fresh_route(&cache, &cloud, &key, 60));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_ordered_lines() {
+        let lines = OrderedLines::default();
+        let mut written = Vec::new();
+        lines.push(1, "b".to_string(), |line| written.push(line.to_string()));
+        lines.push(2, "c".to_string(), |line| written.push(line.to_string()));
+        assert!(written.is_empty());
+        lines.push(0, "a".to_string(), |line| written.push(line.to_string()));
+        assert_eq!(vec!["a", "b", "c"], written);
+        lines.push(3, "d".to_string(), |line| written.push(line.to_string()));
+        assert_eq!(vec!["a", "b", "c", "d"], written);
     }
 }
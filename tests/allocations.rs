@@ -0,0 +1,102 @@
+//! The allocations of the solvers, counted by the global allocator of this test alone
+//! so the other tests run on the system one
+
+/// Counts the allocations of every thread apart
+struct CountingAllocator;
+thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The number of the allocations and reallocations the function makes on this thread
+fn allocations<T>(f: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATIONS.with(std::cell::Cell::get);
+    let result = f();
+    (ALLOCATIONS.with(std::cell::Cell::get) - before, result)
+}
+
+/// The sources of the tool, the solvers are private to it
+#[allow(dead_code)]
+mod best_route {
+    pub fn test_solver_allocations() {
+        use super::allocations;
+
+        let items = (1..=200)
+            .map(|id| {
+                let x = (id * 7919 % 1000) as f64 * 1e6;
+                let z = (id * 104729 % 1000) as f64 * 1e6;
+                (
+                    id,
+                    format!("System I - Asteroid Belt {id}"),
+                    Position::new(&x, &0.0, &z),
+                )
+            })
+            .collect::<Vec<(i32, String, Position)>>();
+
+        let mut cloud = Cloud::new();
+        cloud.add_many(&items[..100]);
+        let (id, name, position) = &items[100];
+        let place = Place::new(id, name, position).unwrap();
+        let (added, _) = allocations(|| cloud.add_place(place));
+        // The row of the new place grown one distance at a time as before
+        let (row, _) = allocations(|| {
+            let mut row = HashMap::new();
+            for other in cloud.ids() {
+                row.insert(other, 0.0);
+            }
+            row
+        });
+        assert!(added < row, "{added} allocations to add a place");
+
+        let points = cloud.ids();
+        let tail = points[1..].to_vec();
+        let rank = |_: &i32| 0;
+        let (presized, walked) = allocations(|| {
+            cloud.lazzy_walker_impl(
+                Cloud::route_from(points[0], points.len()),
+                tail.clone(),
+                &rank,
+            )
+        });
+        let (grown, _) =
+            allocations(|| cloud.lazzy_walker_impl(vec![points[0]], tail.clone(), &rank));
+        assert!(
+            presized < grown,
+            "{presized} allocations of the walk, {grown} before"
+        );
+        assert_eq!(points.len(), walked.1.len());
+
+        // The brute force allocates by the heads rather than by the routes it measures
+        let pool = thread_pool(1).unwrap();
+        let brute = |n: usize| {
+            let mut small = Cloud::new();
+            small.add_many(&items[..n]);
+            let points = small.ids();
+            pool.install(|| allocations(|| small.brute_force(&points, None, None)).0)
+        };
+        let (seven, eight) = (brute(7), brute(8));
+        assert!(
+            eight < permutations(8, false) as usize / 100,
+            "{eight} allocations"
+        );
+        assert!(eight - seven < 10, "{seven} and {eight} allocations");
+    }
+
+    include!("../src/main.rs");
+}
+
+fn main() {
+    best_route::test_solver_allocations();
+    println!("test test_solver_allocations ... ok");
+}
@@ -0,0 +1,806 @@
+use httpmock::prelude::*;
+use httpmock::Mock;
+use std::process::Command;
+
+fn belt(name: &str, x: f64) -> String {
+    format!(
+        r#"{{"name": "{name}", "position": {{"x": {x}, "y": 0.0, "z": 0.0}}, "system_id": 30000001}}"#
+    )
+}
+
+/// The belts of the planets I and III of tests/fixtures/system.json
+const TANOO_BELTS: [(i32, &str, f64); 3] = [
+    (40000003, "Tanoo I - Asteroid Belt 1", 0.0),
+    (40000008, "Tanoo III - Asteroid Belt 1", 0.0),
+    (40000009, "Tanoo III - Asteroid Belt 2", 5000000.0),
+];
+
+/// Mocks the system Tanoo of the fixture and its belts by the ids, names and x coordinates
+fn mock_tanoo<'a>(server: &'a MockServer, belts: &[(i32, &str, f64)]) -> (Mock<'a>, Vec<Mock<'a>>) {
+    let system = server.mock(|when, then| {
+        when.method(GET).path("/universe/systems/30000001/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(include_str!("fixtures/system.json"));
+    });
+    let belts = belts
+        .iter()
+        .map(|&(id, name, x)| {
+            server.mock(|when, then| {
+                when.method(GET)
+                    .path(format!("/universe/asteroid_belts/{id}/"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(belt(name, x));
+            })
+        })
+        .collect();
+    (system, belts)
+}
+
+#[test]
+fn test_route_system_from_mock_server() {
+    let server = MockServer::start();
+
+    let ids = server.mock(|when, then| {
+        when.method(POST).path("/universe/ids/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"systems": [{"id": 30000001, "name": "Tanoo"}]}"#);
+    });
+    let (system, belts) = mock_tanoo(&server, &TANOO_BELTS);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "Tanoo"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Warp to `Tanoo I - Asteroid Belt 1`"));
+    assert!(stdout.contains("Warp to `Tanoo III - Asteroid Belt 2` - 5 Mm"));
+    assert!(stdout.contains("The length of the route: 5 Mm"));
+
+    ids.assert();
+    system.assert();
+    for mock in &belts {
+        mock.assert();
+    }
+}
+
+#[test]
+fn test_resolve_only_prints_all_categories() {
+    let server = MockServer::start();
+    let ids = server.mock(|when, then| {
+        when.method(POST).path("/universe/ids/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(include_str!("fixtures/universe_ids.json"));
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--resolve-only", "Tanoo"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("constellations:\n\t20000001 - San Matar\n"));
+    assert!(stdout.contains("regions:\n\t10000001 - Derelik\n"));
+    assert!(stdout.contains("systems:\n\t30000001 - Tanoo\n\t30000142 - Jita\n"));
+    assert!(!stdout.contains("Warp to"));
+    ids.assert();
+}
+
+#[test]
+fn test_json_output_of_many_systems_is_one_document() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/universe/ids/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(include_str!("fixtures/universe_ids.json"));
+    });
+    mock_tanoo(&server, &TANOO_BELTS);
+    server.mock(|when, then| {
+        when.method(GET).path("/universe/systems/30000142/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"constellation_id": 20000020, "name": "Jita", "security_status": 0.9,
+                    "planets": [{"planet_id": 40009077, "asteroid_belts": [40009078]}],
+                    "system_id": 30000142}"#,
+            );
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/universe/asteroid_belts/40009078/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(belt("Jita IV - Asteroid Belt 1", 0.0));
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--format", "json"])
+        .args(["Tanoo", "Jita"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let systems = reports.as_array().unwrap();
+    assert_eq!(2, systems.len());
+    assert_eq!("Tanoo", systems[0]["name"]);
+    assert_eq!(2, systems[0]["clouds"].as_array().unwrap().len());
+    assert_eq!(5000000.0, systems[0]["clouds"][1]["distance"]);
+    assert_eq!("Jita", systems[1]["name"]);
+    assert_eq!(
+        "Jita IV - Asteroid Belt 1",
+        systems[1]["clouds"][0]["route"][0]["name"]
+    );
+}
+
+#[test]
+fn test_duplicate_systems_are_routed_once() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/universe/ids/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"systems": [{"id": 30000001, "name": "Tanoo"}]}"#);
+    });
+    let system = server.mock(|when, then| {
+        when.method(GET).path("/universe/systems/30000001/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"constellation_id": 20000001, "name": "Tanoo", "security_status": 0.8, "system_id": 30000001}"#);
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--format", "json"])
+        .args(["Tanoo", "tanoo", "30000001"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(1, reports.as_array().unwrap().len());
+    system.assert_hits(1);
+}
+
+#[test]
+fn test_text_and_json_outputs_from_one_run() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/universe/ids/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"systems": [{"id": 30000001, "name": "Tanoo"}]}"#);
+    });
+    let system = server.mock(|when, then| {
+        when.method(GET).path("/universe/systems/30000001/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"constellation_id": 20000001, "name": "Tanoo", "security_status": 0.8,
+                    "planets": [{"planet_id": 40000002, "asteroid_belts": [40000003, 40000004]}],
+                    "system_id": 30000001}"#,
+            );
+    });
+    for (id, name, x) in [
+        (40000003, "Tanoo I - Asteroid Belt 1", 0.0),
+        (40000004, "Tanoo I - Asteroid Belt 2", 7000000.0),
+    ] {
+        server.mock(|when, then| {
+            when.method(GET)
+                .path(format!("/universe/asteroid_belts/{id}/"));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(belt(name, x));
+        });
+    }
+
+    let json = std::env::temp_dir().join(format!("best_route_{}.json", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url()])
+        .args(["--format", "text", "--format", "json"])
+        .args(["--json-to", json.to_str().unwrap(), "Tanoo"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Warp to `Tanoo I - Asteroid Belt 2` - 7 Mm"));
+    assert!(!stdout.contains('{'));
+
+    let reports: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    std::fs::remove_file(&json).unwrap();
+    assert_eq!(7000000.0, reports[0]["clouds"][0]["distance"]);
+    system.assert_hits(1);
+}
+
+#[test]
+fn test_limit_caps_the_routed_systems() {
+    let server = MockServer::start();
+    let systems = [30000001, 30000002, 30000003].map(|id| {
+        server.mock(|when, then| {
+            when.method(GET).path(format!("/universe/systems/{id}/"));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(format!(
+                    r#"{{"constellation_id": 20000001, "name": "System {id}", "security_status": 0.8, "system_id": {id}}}"#
+                ));
+        })
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--format", "json"])
+        .args(["--limit", "2", "30000001", "30000002", "30000003"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(2, reports.as_array().unwrap().len());
+    assert_eq!(30000002, reports[1]["system_id"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Routing 2 of 3 systems, 1 skipped"));
+    systems[0].assert_hits(1);
+    systems[1].assert_hits(1);
+    systems[2].assert_hits(0);
+}
+
+#[test]
+fn test_bookmarks_of_every_routed_belt() {
+    let server = MockServer::start();
+    let belts = [
+        (40000003, "Tanoo I - Asteroid Belt 1", 0.0),
+        (40000008, "Tanoo III - Asteroid Belt 1", 2000000.0),
+        (40000009, "Tanoo III - Asteroid Belt 2", 5000000.0),
+    ];
+    mock_tanoo(&server, &belts);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--format", "bookmarks"])
+        .arg("30000001")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let bookmarks: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let bookmarks = bookmarks.as_array().unwrap();
+    assert_eq!(3, bookmarks.len());
+    assert_eq!("Tanoo 1.01", bookmarks[0]["label"]);
+    assert_eq!("Tanoo 2.02", bookmarks[2]["label"]);
+    for (id, name, x) in belts {
+        let bookmark = bookmarks
+            .iter()
+            .find(|bookmark| bookmark["item_id"] == id)
+            .unwrap();
+        assert_eq!(name, bookmark["name"]);
+        assert_eq!(30000001, bookmark["system_id"]);
+        assert_eq!(x, bookmark["x"]);
+        assert_eq!(0.0, bookmark["y"]);
+        assert_eq!(0.0, bookmark["z"]);
+    }
+}
+
+#[test]
+fn test_color_never_prints_no_escape_codes() {
+    let server = MockServer::start();
+    mock_tanoo(&server, &TANOO_BELTS);
+
+    let run = |color: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url(), "--color", color])
+            .arg("30000001")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let plain = run("never");
+    assert!(plain.contains("The length of the route: 5 Mm"));
+    assert!(!plain.contains('\x1b'));
+    assert!(run("always").contains('\x1b'));
+}
+
+#[test]
+fn test_interactive_mode_routes_every_line() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let server = MockServer::start();
+    let ids = server.mock(|when, then| {
+        when.method(POST)
+            .path("/universe/ids/")
+            .json_body(serde_json::json!(["Tanoo"]));
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"systems": [{"id": 30000001, "name": "Tanoo"}]}"#);
+    });
+    let unknown = server.mock(|when, then| {
+        when.method(POST)
+            .path("/universe/ids/")
+            .json_body(serde_json::json!(["Nowhere"]));
+        then.status(200)
+            .header("content-type", "application/json")
+            .body("{}");
+    });
+    let (system, _) = mock_tanoo(&server, &TANOO_BELTS);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--interactive"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"Tanoo\n\n  \nset solver bogus\nset planets\nset number-style grouped\nNowhere\n30000001\nquit\nTanoo\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(4, stdout.matches("The length of the route: 5 Mm").count());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Error: invalid variant: bogus"));
+    assert!(stderr.contains("Error: Usage: set OPTION VALUE"));
+    assert!(stderr.contains("Error: Unknown system: Nowhere"));
+    // Nothing is read after quit
+    ids.assert_hits(1);
+    unknown.assert_hits(1);
+    system.assert_hits(2);
+}
+
+#[test]
+fn test_route_ends_at_the_station() {
+    let server = MockServer::start();
+    mock_tanoo(
+        &server,
+        &[
+            (40000003, "Tanoo I - Asteroid Belt 1", 0.0),
+            (40000008, "Tanoo III - Asteroid Belt 1", 0.0),
+            (40000009, "Tanoo III - Asteroid Belt 2", 0.0),
+        ],
+    );
+    let station = server.mock(|when, then| {
+        when.method(GET).path("/universe/stations/60012526/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"name": "Tanoo V - Moon 1 - Station", "position": {"x": 85000000.0, "y": 0.0, "z": 0.0}, "system_id": 30000001}"#,
+            );
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url()])
+        .args(["--end-at-station", "60012526", "30000001"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // Both the ordinal and the shortest routes of both clouds
+    assert_eq!(4, stdout.matches("Return to station - 85 Mm").count());
+    assert!(stdout.contains("The length of the route: 85 Mm"));
+    station.assert();
+}
+
+#[test]
+fn test_ndjson_line_per_cloud() {
+    let server = MockServer::start();
+    mock_tanoo(&server, &TANOO_BELTS);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--format", "ndjson"])
+        .arg("30000001")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(2, records.len());
+    for record in &records {
+        assert_eq!(30000001, record["system_id"]);
+        assert_eq!("Tanoo", record["system_name"]);
+    }
+    assert_eq!(40000002, records[0]["planet_id"]);
+    assert_eq!(1, records[0]["route"].as_array().unwrap().len());
+    assert_eq!(40000007, records[1]["planet_id"]);
+    assert_eq!(5000000.0, records[1]["distance"]);
+    assert_eq!(2, records[1]["route"].as_array().unwrap().len());
+}
+
+#[test]
+fn test_planet_order() {
+    let server = MockServer::start();
+    // The names sort the outer planet first
+    mock_tanoo(
+        &server,
+        &[
+            (40000003, "Tanoo III - Asteroid Belt 1", 0.0),
+            (40000008, "Tanoo I - Asteroid Belt 1", 0.0),
+            (40000009, "Tanoo I - Asteroid Belt 2", 5000000.0),
+        ],
+    );
+    let route = |flags: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url(), "--format", "ndjson"])
+            .args(flags)
+            .arg("30000001")
+            .output()
+            .unwrap()
+    };
+    let planets = |flags: &[&str]| {
+        let output = route(flags);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["planet_id"].clone()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(vec![40000007, 40000002], planets(&["--order-by", "name"]));
+    assert_eq!(vec![40000002, 40000007], planets(&["--planet-order"]));
+
+    // The flag doesn't override an explicit order silently
+    let output = route(&["--order-by", "name", "--planet-order"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("cannot be used with"));
+}
+
+#[test]
+fn test_belt_whitelist_keeps_the_listed_belts() {
+    let server = MockServer::start();
+    mock_tanoo(&server, &TANOO_BELTS);
+    let whitelist =
+        std::env::temp_dir().join(format!("best_route_whitelist_{}", std::process::id()));
+    std::fs::write(
+        &whitelist,
+        "# The outer planet only\n40000009\n\n40000008\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--format", "ndjson"])
+        .arg("--belt-whitelist-file")
+        .arg(&whitelist)
+        .arg("30000001")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&whitelist).ok();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(1, records.len());
+    assert_eq!(40000007, records[0]["planet_id"]);
+    let mut route = records[0]["route"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hop| hop["id"].as_i64().unwrap())
+        .collect::<Vec<_>>();
+    route.sort();
+    assert_eq!(vec![40000008, 40000009], route);
+}
+
+#[test]
+fn test_prewarm_routes_without_the_esi() {
+    let server = MockServer::start();
+    let ids = server.mock(|when, then| {
+        when.method(POST).path("/universe/ids/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"systems": [{"id": 30000001, "name": "Tanoo"}]}"#);
+    });
+    let (system, mut mocks) = mock_tanoo(&server, &TANOO_BELTS);
+    mocks.extend([ids, system]);
+    let cache = std::env::temp_dir().join(format!("best_route_prewarm_{}", std::process::id()));
+    std::fs::remove_dir_all(&cache).ok();
+    let run = |flags: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url(), "--retries", "0"])
+            .arg("--cache")
+            .arg(&cache)
+            .args(flags)
+            .arg("Tanoo")
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--prewarm"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Cached 1 systems and 3 belts"));
+    for mock in &mocks {
+        mock.assert_hits(1);
+    }
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Warp to `Tanoo III - Asteroid Belt 2` - 5 Mm"));
+    // No more requests than the prewarm made
+    for mock in &mocks {
+        mock.assert_hits(1);
+    }
+
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[test]
+fn test_wormhole_without_belts() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(GET).path("/universe/systems/31000005/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(
+                r#"{"constellation_id": 21000001, "name": "J105443", "security_class": "",
+                    "planets": [{"planet_id": 40000101}, {"planet_id": 40000102, "moons": [40000103]}],
+                    "security_status": -0.99, "system_id": 31000005}"#,
+            );
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args([
+            "--esi-base",
+            &server.base_url(),
+            "--strict",
+            "--format",
+            "json",
+        ])
+        .arg("31000005")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("The wormhole system J105443 has no asteroid belts to route"));
+    assert!(stderr.contains("--planets"));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!("J105443", report[0]["name"]);
+    assert!(report[0]["clouds"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_audit_fails_on_violations() {
+    let server = MockServer::start();
+    // The system 30000002 comes as the system 30000001
+    server.mock(|when, then| {
+        when.method(GET).path("/universe/systems/30000002/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(include_str!("fixtures/system.json"));
+    });
+    mock_tanoo(&server, &TANOO_BELTS[..2]);
+    server.mock(|when, then| {
+        when.method(GET).path("/universe/asteroid_belts/40000009/");
+        then.status(404);
+    });
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--esi-base", &server.base_url(), "--audit"])
+        .args(["30000002", "30000001"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Tanoo (30000002): 3 violations"));
+    assert!(stdout.contains("The system 30000002 came as the system 30000001"));
+    assert!(stdout.contains("Tanoo (30000001): 2 violations"));
+    assert!(stdout.contains("Failed to load the asteroid belt 40000009"));
+    assert!(stdout.contains("The belts 40000003 and 40000008 are at the same position"));
+    assert!(!stdout.contains("Warp to"));
+}
+
+#[test]
+fn test_cache_resumes_an_interrupted_load() {
+    let server = MockServer::start();
+    let (system, cached) = mock_tanoo(&server, &TANOO_BELTS[..2]);
+    let mut missing = server.mock(|when, then| {
+        when.method(GET).path("/universe/asteroid_belts/40000009/");
+        then.status(503);
+    });
+
+    let cache = std::env::temp_dir().join(format!("best_route_cache_{}", std::process::id()));
+    std::fs::remove_dir_all(&cache).ok();
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url(), "--retries", "0"])
+            .arg("--cache")
+            .arg(&cache)
+            .arg("30000001")
+            .output()
+            .unwrap()
+    };
+
+    // The first run fails on the last belt after caching the others
+    assert!(!run().status.success());
+    missing.assert_hits(1);
+    missing.delete();
+    let missing = server.mock(|when, then| {
+        when.method(GET).path("/universe/asteroid_belts/40000009/");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(belt("Tanoo III - Asteroid Belt 2", 5000000.0));
+    });
+
+    let output = run();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Warp to `Tanoo III - Asteroid Belt 2` - 5 Mm"));
+    // The system is cached as well
+    system.assert_hits(1);
+    for mock in &cached {
+        mock.assert_hits(1);
+    }
+    missing.assert_hits(1);
+
+    std::fs::remove_dir_all(&cache).unwrap();
+}
+
+#[test]
+fn test_belt_positions_file_overrides_the_esi() {
+    let server = MockServer::start();
+    mock_tanoo(&server, &TANOO_BELTS);
+    let distance = |args: &[&std::ffi::OsStr]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url(), "--format", "ndjson"])
+            .args(args)
+            .arg("30000001")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let record = stdout
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|record| record["planet_id"] == 40000007)
+            .unwrap();
+        record["distance"].as_f64().unwrap()
+    };
+
+    for (name, text) in [
+        ("csv", "id,x,y,z\n40000009,2500000,0,0\n"),
+        (
+            "json",
+            r#"{"40000009": {"x": 2500000.0, "y": 0.0, "z": 0.0}}"#,
+        ),
+    ] {
+        let path = std::env::temp_dir().join(format!(
+            "best_route_positions_{}.{name}",
+            std::process::id()
+        ));
+        std::fs::write(&path, text).unwrap();
+        let esi = distance(&["--belt-positions".as_ref(), path.as_os_str()]);
+        let file = distance(&[
+            "--belt-position-source".as_ref(),
+            "file".as_ref(),
+            "--belt-positions".as_ref(),
+            path.as_os_str(),
+        ]);
+        std::fs::remove_file(&path).ok();
+        assert!(esi > 0.0);
+        assert_eq!(esi / 2.0, file);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+        .args(["--belt-position-source", "file", "30000001"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_route_cache_ttl_reuses_the_fresh_routes() {
+    let server = MockServer::start();
+    mock_tanoo(&server, &TANOO_BELTS);
+    let dir = std::env::temp_dir().join(format!("best_route_route_cache_{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let route = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url(), "--format", "ndjson"])
+            .arg("--cache")
+            .arg(&dir)
+            .args(["--route-cache-ttl", "3600", "30000001"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let record = stdout
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|record| record["planet_id"] == 40000007)
+            .unwrap();
+        record["route"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hop| hop["id"].as_i64().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let solved = route();
+    let path = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            name.starts_with("route_")
+                && std::fs::read_to_string(path).unwrap().contains("40000008")
+        })
+        .unwrap();
+    let cached = |solved_at: u64| {
+        let reversed = solved.iter().rev().collect::<Vec<_>>();
+        let entry = serde_json::json!({"solved_at": solved_at, "route": reversed});
+        std::fs::write(&path, entry.to_string()).unwrap();
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // The fresh route is reused
+    cached(now);
+    let reversed = solved.iter().rev().cloned().collect::<Vec<_>>();
+    assert_eq!(reversed, route());
+
+    // The expired one is solved again
+    cached(now - 3601);
+    assert_eq!(solved, route());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_broken_belt_files_fail_before_the_esi() {
+    let server = MockServer::start();
+    let (system, _) = mock_tanoo(&server, &[]);
+    let path = std::env::temp_dir().join(format!("best_route_broken_{}.csv", std::process::id()));
+    for (flags, text, error) in [
+        (
+            vec!["--belt-whitelist-file"],
+            "40000009\nforty\n",
+            "`forty`",
+        ),
+        (
+            vec!["--belt-position-source", "file", "--belt-positions"],
+            "40000009,1,2\n",
+            "`40000009,1,2`",
+        ),
+        (vec!["--import-route"], "40000009 forty\n", "`forty`"),
+    ] {
+        std::fs::write(&path, text).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_best_route"))
+            .args(["--esi-base", &server.base_url()])
+            .args(flags)
+            .arg(&path)
+            .args(["30000001", "30000002"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(error), "{stderr}");
+    }
+    std::fs::remove_file(&path).ok();
+    system.assert_hits(0);
+}